@@ -1,31 +1,23 @@
 use modular_core::{
-    Callback, CallbackError, CallbackSuccess, Module, NativeModule, NativeRegistry, Registry,
+    module, Callback, CallbackError, CallbackSuccess, InvocationContext, NativeRegistry, Registry,
 };
-use native_recorder::{register_module_tracer, NativeBytesRecorder};
 use tracing::{error, info, instrument};
 
 struct Module1 {
     registry: NativeRegistry,
 }
 
+#[module(
+    package = "dll.module1",
+    version = "1.0.0",
+    dependencies = [("dll.module2", "^0.0.1")]
+)]
 impl Module1 {
     #[instrument(skip(registry))]
     pub fn new(registry: NativeRegistry) -> Self {
         info!("hello from module1");
         Self { registry }
     }
-}
-
-impl Module for Module1 {
-    #[instrument(skip(self))]
-    fn package(&self) -> &str {
-        "dll.module1"
-    }
-
-    #[instrument(skip(self))]
-    fn version(&self) -> &str {
-        "1.0.0"
-    }
 
     #[instrument(skip(self))]
     fn run(&self) {
@@ -57,28 +49,17 @@ impl Module for Module1 {
         }
 
         self.registry
-            .invoke("dll.module2", "1", None, Box::new(TestCallback {}))
+            .invoke("dll.module2", "1", None, Box::new(TestCallback {}));
     }
 
-    #[instrument(skip(self, callback))]
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
+    /// Greets the wasm example.
+    #[method(name = "invoke from wasm")]
+    #[instrument(skip(self, context))]
+    fn invoke_from_wasm(&self, context: InvocationContext) -> String {
         info!(
-            "dll.module1::invoke: method = {}, data = {:?}",
-            method, data
+            "dll.module1::invoke: caller = {:?}, id = {}",
+            context.caller, context.id
         );
-        callback.on_success(CallbackSuccess {
-            data: Some(b"dll.module1::invoke"),
-        });
+        "dll.module1::invoke".to_string()
     }
 }
-
-#[instrument(skip(registry, recorder))]
-#[no_mangle]
-pub extern "C" fn create_module(
-    registry: NativeRegistry,
-    recorder: NativeBytesRecorder,
-) -> NativeModule {
-    register_module_tracer(Box::leak(Box::new(recorder)));
-
-    NativeModule::new(Module1::new(registry))
-}
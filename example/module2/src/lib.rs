@@ -1,5 +1,6 @@
 use modular_core::{
-    Callback, CallbackError, CallbackSuccess, Module, NativeModule, NativeRegistry, Registry,
+    Callback, CallbackError, CallbackSuccess, CancellationToken, InvocationContext, Module,
+    NativeAbi, NativeAbiHeader, NativeModule, NativeRegistry, Registry,
 };
 use native_recorder::{register_module_tracer, NativeBytesRecorder};
 use tracing::{error, info, instrument};
@@ -32,8 +33,15 @@ impl Module for Module2 {
         info!("dll.module2::run");
     }
 
-    #[instrument(skip(self, callback))]
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
+    #[instrument(skip(self, callback, _token, _context))]
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        _token: CancellationToken,
+        _context: InvocationContext,
+    ) {
         struct TestCallback {}
 
         impl Callback for TestCallback {
@@ -82,7 +90,16 @@ pub extern "C" fn create_module(
     registry: NativeRegistry,
     recorder: NativeBytesRecorder,
 ) -> NativeModule {
-    register_module_tracer(Box::leak(Box::new(recorder)));
+    let registry = registry.expect_abi();
+    let recorder = recorder.expect_abi();
+
+    // a module loaded again keeps recording with the tracer it registered the first time
+    let _ = register_module_tracer(Box::leak(Box::new(recorder)));
 
     NativeModule::new(Module2::new(registry))
 }
+
+#[no_mangle]
+pub extern "C" fn modular_abi() -> NativeAbiHeader {
+    NativeModule::abi()
+}
@@ -1,39 +1,25 @@
-use modular::{Modular, NativeRegistry, Registry};
-use modular_dll::DllModule;
-use modular_tracing_core::{register_module_tracer, LazyBytesRecorder, LazyRecorder};
-use modular_wasm::WasmModule;
+use modular::{Modular, Registry};
 
 fn main() {
-    let recorder = modular_tracing_core::DefaultRecorder::new();
-    let recorder = Box::leak(Box::new(recorder));
-    let (runner, receiver) = LazyRecorder::new(recorder);
-    register_module_tracer(recorder);
-
-    runner.run();
-
-    let modular = Modular::default();
+    let (modular, report) = Modular::from_config("example/host.toml").unwrap();
+    for (path, e) in &report.failed {
+        eprintln!("failed to load {}: {}", path.display(), e);
+    }
 
     // let (modular, _lib) = unsafe {
     //     let lib = libloading::Library::new("target/debug/libmodular.dylib").unwrap();
     //     let create_modular = lib
-    //         .get::<extern "C" fn() -> NativeRegistry>(b"create_modular")
+    //         .get::<extern "C" fn() -> modular::NativeRegistry>(b"create_modular")
     //         .unwrap();
     //
     //     (create_modular(), lib)
     // };
 
-    let module1 =
-        DllModule::new("target/debug/libmodule1.dylib", &modular, receiver.clone()).unwrap();
-    let module2 =
-        DllModule::new("target/debug/libmodule2.dylib", &modular, receiver.clone()).unwrap();
-    // let module3 = WasmModule::new(
+    // let module3 = modular_wasm::WasmModule::new(
     //     include_bytes!("../../target/wasm32-wasi/debug/wasm_example.wasm"),
-    //     modular.clone(),
+    //     modular.scoped("wasm-example.module1"),
     // )
     // .unwrap();
-
-    modular.register_module(Box::new(module1));
-    modular.register_module(Box::new(module2));
     // modular.register_module(Box::new(module3));
 
     let _ = modular.run();
@@ -0,0 +1,107 @@
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use tracing::error;
+
+/// Version of the `#[repr(C)]` layouts shared between the host and modules.
+/// Must be bumped every time one of the native structs changes.
+pub const ABI_VERSION: u32 = 16;
+
+/// Symbol of the `extern "C" fn() -> NativeAbiHeader` a dll module exports, returning
+/// `NativeModule::abi()`. The host checks it before passing the module anything, so that
+/// no struct of another layout crosses the boundary. `NativeAbiHeader` itself never changes.
+/// Each side still checks the header of every vtable it receives, see `NativeAbi`.
+pub const ABI_SYMBOL: &[u8] = b"modular_abi";
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct NativeAbiHeader {
+    pub version: u32,
+    pub size: u32,
+}
+
+impl NativeAbiHeader {
+    pub const fn new<T>() -> Self {
+        Self {
+            version: ABI_VERSION,
+            size: std::mem::size_of::<T>() as u32,
+        }
+    }
+
+    pub fn check<T>(&self, name: &'static str) -> Result<(), AbiMismatch> {
+        let expected = Self::new::<T>();
+
+        if *self == expected {
+            Ok(())
+        } else {
+            Err(AbiMismatch {
+                name,
+                expected,
+                found: *self,
+            })
+        }
+    }
+
+    /// Checks only the version, for layouts whose size is not comparable
+    /// with the host one (e.g. structs living inside wasm32 guest memory).
+    pub fn check_version(&self, name: &'static str) -> Result<(), AbiMismatch> {
+        if self.version == ABI_VERSION {
+            Ok(())
+        } else {
+            Err(AbiMismatch {
+                name,
+                expected: NativeAbiHeader {
+                    version: ABI_VERSION,
+                    size: self.size,
+                },
+                found: *self,
+            })
+        }
+    }
+}
+
+/// Implemented by every `#[repr(C)]` struct crossing the module boundary.
+/// The header must be the first field so it can be read regardless of the rest of the layout.
+/// A struct failing `check_abi` must be leaked rather than dropped, as its vtable can't be trusted.
+pub trait NativeAbi: Sized {
+    const NAME: &'static str;
+
+    fn abi_header(&self) -> NativeAbiHeader;
+
+    fn check_abi(&self) -> Result<(), AbiMismatch> {
+        self.abi_header().check::<Self>(Self::NAME)
+    }
+
+    /// Checks a struct received where no error can be reported, as the arguments of
+    /// `create_module`, aborting on a mismatch since it can be neither used nor dropped.
+    fn expect_abi(self) -> Self {
+        if let Err(e) = self.check_abi() {
+            error!("{}", e);
+            std::process::abort();
+        }
+
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AbiMismatch {
+    pub name: &'static str,
+    pub expected: NativeAbiHeader,
+    pub found: NativeAbiHeader,
+}
+
+impl Display for AbiMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} abi mismatch: expected version {} (size {}), found version {} (size {})",
+            self.name,
+            self.expected.version,
+            self.expected.size,
+            self.found.version,
+            self.found.size
+        )
+    }
+}
+
+impl StdError for AbiMismatch {}
@@ -0,0 +1,206 @@
+use crate::errors::Error;
+use crate::*;
+use futures::channel::{mpsc, oneshot};
+use futures::Stream;
+use parking_lot::Mutex;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub type InvokeResult = Result<OwnedSuccess, OwnedError>;
+
+/// `async` counterpart of `Registry::invoke`, available on every registry.
+pub trait AsyncRegistry: Registry {
+    fn invoke_async(&self, package: &str, method: &str, data: Option<&[u8]>) -> InvokeFuture {
+        self.invoke_async_with(package, method, data, InvokeOptions::default())
+    }
+
+    fn invoke_async_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        options: InvokeOptions,
+    ) -> InvokeFuture {
+        let (tx, rx) = oneshot::channel();
+
+        let callback = FutureCallback {
+            tx: Mutex::new(Some(tx)),
+        };
+        let token = self.invoke_with(package, method, data, Box::new(callback), options);
+
+        InvokeFuture {
+            rx,
+            token,
+            done: false,
+        }
+    }
+
+    /// `futures::Stream` counterpart of `Registry::invoke_stream`. The stream ends after
+    /// `on_complete`, or after yielding the error passed to `on_error`.
+    fn invoke_stream_async(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+    ) -> InvokeStream {
+        self.invoke_stream_async_with(package, method, data, InvokeOptions::default())
+    }
+
+    fn invoke_stream_async_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        options: InvokeOptions,
+    ) -> InvokeStream {
+        // unbounded, since a callee may stream synchronously, before `invoke_stream_with`
+        // returns and the stream can be polled
+        let (tx, rx) = mpsc::unbounded();
+
+        let callback = Box::new(ChannelStreamCallback { tx });
+        let token = self.invoke_stream_with(package, method, data, callback, options);
+
+        InvokeStream {
+            rx,
+            token,
+            done: false,
+        }
+    }
+}
+
+impl<R: Registry> AsyncRegistry for R {}
+
+struct FutureCallback {
+    tx: Mutex<Option<oneshot::Sender<InvokeResult>>>,
+}
+
+impl FutureCallback {
+    fn complete(&self, result: InvokeResult) {
+        if let Some(tx) = self.tx.lock().take() {
+            // the future may have been dropped already, nobody to notify then
+            let _ = tx.send(result);
+        }
+    }
+}
+
+impl Callback for FutureCallback {
+    fn on_success(&self, result: CallbackSuccess) {
+        self.complete(Ok(result.into()));
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.complete(Err(err.into()));
+    }
+}
+
+enum StreamMessage {
+    Next(OwnedSuccess),
+    Complete,
+    Error(OwnedError),
+}
+
+struct ChannelStreamCallback {
+    tx: mpsc::UnboundedSender<StreamMessage>,
+}
+
+impl StreamCallback for ChannelStreamCallback {
+    fn on_next(&self, result: CallbackSuccess) {
+        let _ = self.tx.unbounded_send(StreamMessage::Next(result.into()));
+    }
+
+    fn on_complete(&self) {
+        let _ = self.tx.unbounded_send(StreamMessage::Complete);
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        let _ = self.tx.unbounded_send(StreamMessage::Error(err.into()));
+    }
+}
+
+/// Dropping the future before it resolves cancels the invocation.
+pub struct InvokeFuture {
+    rx: oneshot::Receiver<InvokeResult>,
+    token: CancellationToken,
+    done: bool,
+}
+
+impl InvokeFuture {
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Future for InvokeFuture {
+    type Output = InvokeResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(v) => v,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        self.done = true;
+        Poll::Ready(result.unwrap_or_else(|_| Err(Error::CallbackDropped.into())))
+    }
+}
+
+impl Drop for InvokeFuture {
+    fn drop(&mut self) {
+        if !self.done {
+            self.token.cancel();
+        }
+    }
+}
+
+/// Dropping the stream before it ends cancels the invocation.
+pub struct InvokeStream {
+    rx: mpsc::UnboundedReceiver<StreamMessage>,
+    token: CancellationToken,
+    done: bool,
+}
+
+impl InvokeStream {
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Stream for InvokeStream {
+    type Item = InvokeResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        let message = match Pin::new(&mut self.rx).poll_next(cx) {
+            Poll::Ready(v) => v,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        Poll::Ready(match message {
+            Some(StreamMessage::Next(v)) => Some(Ok(v)),
+            Some(StreamMessage::Complete) => {
+                self.done = true;
+                None
+            }
+            Some(StreamMessage::Error(e)) => {
+                self.done = true;
+                Some(Err(e))
+            }
+            None => {
+                self.done = true;
+                Some(Err(Error::CallbackDropped.into()))
+            }
+        })
+    }
+}
+
+impl Drop for InvokeStream {
+    fn drop(&mut self) {
+        if !self.done {
+            self.token.cancel();
+        }
+    }
+}
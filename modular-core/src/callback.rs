@@ -1,4 +1,8 @@
+use crate::errors::Error;
 use crate::*;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use tracing::error;
 
 pub trait Callback: Send + Sync {
     fn on_success(&self, result: CallbackSuccess);
@@ -15,8 +19,19 @@ impl Callback for Box<dyn Callback> {
     }
 }
 
+impl<C: Callback + ?Sized> Callback for Arc<C> {
+    fn on_success(&self, result: CallbackSuccess) {
+        (**self).on_success(result);
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        (**self).on_error(err);
+    }
+}
+
 #[repr(C)]
 pub struct NativeCallback {
+    abi: NativeAbiHeader,
     instance: *mut (),
     on_success: extern "C" fn(*mut (), NativeCallbackSuccess),
     on_error: extern "C" fn(*mut (), NativeCallbackError),
@@ -31,6 +46,7 @@ impl NativeCallback {
         let instance = Box::into_raw(Box::new(callback)) as *mut ();
 
         Self {
+            abi: NativeAbiHeader::new::<Self>(),
             instance,
             on_success: Self::on_success::<T>,
             on_error: Self::on_error::<T>,
@@ -40,17 +56,34 @@ impl NativeCallback {
 
     extern "C" fn on_success<T: Callback>(instance: *mut (), result: NativeCallbackSuccess) {
         let callback = unsafe { &*(instance as *const T) };
-        callback.on_success(result.into());
+
+        if let Err(e) = catch_panic(|| callback.on_success(result.into())) {
+            error!("panic in callback on_success: {}", e);
+        }
     }
 
     extern "C" fn on_error<T: Callback>(instance: *mut (), err: NativeCallbackError) {
         let callback = unsafe { &*(instance as *const T) };
-        callback.on_error(err.into());
+
+        if let Err(e) = catch_panic(|| callback.on_error(err.into())) {
+            error!("panic in callback on_error: {}", e);
+        }
     }
 
     extern "C" fn drop<T: Callback>(instance: *mut ()) {
         let callback = unsafe { Box::from_raw(instance as *mut T) };
-        drop(callback);
+
+        if let Err(e) = catch_panic(|| drop(callback)) {
+            error!("panic in callback drop: {}", e);
+        }
+    }
+}
+
+impl NativeAbi for NativeCallback {
+    const NAME: &'static str = "NativeCallback";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
     }
 }
 
@@ -133,3 +166,79 @@ impl From<NativeCallbackError> for CallbackError<'_> {
         }
     }
 }
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OwnedSuccess {
+    pub data: Option<Vec<u8>>,
+}
+
+impl OwnedSuccess {
+    pub fn as_success(&self) -> CallbackSuccess<'_> {
+        CallbackSuccess {
+            data: self.data.as_deref(),
+        }
+    }
+}
+
+impl From<CallbackSuccess<'_>> for OwnedSuccess {
+    fn from(v: CallbackSuccess) -> Self {
+        Self {
+            data: v.data.map(|i| i.to_vec()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OwnedError {
+    pub code: i32,
+    pub err_name: Option<String>,
+    pub description: Option<String>,
+    pub data: Option<Vec<u8>>,
+}
+
+impl OwnedError {
+    pub fn as_error(&self) -> CallbackError<'_> {
+        CallbackError {
+            code: self.code,
+            err_name: self.err_name.as_deref(),
+            description: self.description.as_deref(),
+            data: self.data.as_deref(),
+        }
+    }
+}
+
+impl From<CallbackError<'_>> for OwnedError {
+    fn from(v: CallbackError) -> Self {
+        Self {
+            code: v.code,
+            err_name: v.err_name.map(|i| i.to_string()),
+            description: v.description.map(|i| i.to_string()),
+            data: v.data.map(|i| i.to_vec()),
+        }
+    }
+}
+
+impl From<Error> for OwnedError {
+    fn from(e: Error) -> Self {
+        Self {
+            code: e as i32,
+            err_name: Some(e.as_ref().to_string()),
+            description: None,
+            data: None,
+        }
+    }
+}
+
+impl Display for OwnedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.err_name.as_deref().unwrap_or("error"),
+            self.code,
+            self.description.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl std::error::Error for OwnedError {}
@@ -0,0 +1,150 @@
+use crate::errors::Error;
+use crate::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::error;
+
+struct Completion {
+    target: String,
+    completed: AtomicBool,
+}
+
+impl Completion {
+    fn new(package: &str, method: &str) -> Self {
+        Self {
+            target: format!("{}::{}", package, method),
+            completed: AtomicBool::new(false),
+        }
+    }
+
+    fn is_completed(&self) -> bool {
+        self.completed.load(Ordering::Acquire)
+    }
+
+    fn check_open(&self, kind: &'static str) -> bool {
+        if self.is_completed() {
+            error!(
+                "callback of {:?} already completed, rejecting {}",
+                self.target, kind
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    fn complete(&self, kind: &'static str) -> bool {
+        if self.completed.swap(true, Ordering::AcqRel) {
+            error!(
+                "callback of {:?} already completed, rejecting {}",
+                self.target, kind
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    fn complete_on_drop(&self, fail: impl FnOnce(CallbackError)) {
+        if !self.completed.swap(true, Ordering::AcqRel) {
+            error!("callback of {:?} dropped without completion", self.target);
+
+            fail(CallbackError {
+                code: Error::CallbackDropped as i32,
+                err_name: Error::CallbackDropped.as_ref().into(),
+                description: Some(&format!(
+                    "callback of {:?} dropped without completion",
+                    self.target
+                )),
+                data: None,
+            });
+        }
+    }
+}
+
+/// Enforces that the wrapped callback is completed exactly once: a second completion is
+/// rejected, and dropping the guard without completing it reports `Error::CallbackDropped`.
+pub struct CallbackGuard<C: Callback = Box<dyn Callback>> {
+    callback: C,
+    completion: Completion,
+}
+
+impl<C: Callback> CallbackGuard<C> {
+    pub fn new(package: &str, method: &str, callback: C) -> Self {
+        Self {
+            callback,
+            completion: Completion::new(package, method),
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.completion.is_completed()
+    }
+}
+
+impl<C: Callback> Callback for CallbackGuard<C> {
+    fn on_success(&self, result: CallbackSuccess) {
+        if self.completion.complete("on_success") {
+            self.callback.on_success(result);
+        }
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        if self.completion.complete("on_error") {
+            self.callback.on_error(err);
+        }
+    }
+}
+
+impl<C: Callback> Drop for CallbackGuard<C> {
+    fn drop(&mut self) {
+        self.completion
+            .complete_on_drop(|err| self.callback.on_error(err));
+    }
+}
+
+/// `CallbackGuard` for streams: `on_next` is accepted until the stream is completed
+/// by `on_complete` or `on_error`, which may happen only once.
+pub struct StreamCallbackGuard<C: StreamCallback = Box<dyn StreamCallback>> {
+    callback: C,
+    completion: Completion,
+}
+
+impl<C: StreamCallback> StreamCallbackGuard<C> {
+    pub fn new(package: &str, method: &str, callback: C) -> Self {
+        Self {
+            callback,
+            completion: Completion::new(package, method),
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.completion.is_completed()
+    }
+}
+
+impl<C: StreamCallback> StreamCallback for StreamCallbackGuard<C> {
+    fn on_next(&self, result: CallbackSuccess) {
+        if self.completion.check_open("on_next") {
+            self.callback.on_next(result);
+        }
+    }
+
+    fn on_complete(&self) {
+        if self.completion.complete("on_complete") {
+            self.callback.on_complete();
+        }
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        if self.completion.complete("on_error") {
+            self.callback.on_error(err);
+        }
+    }
+}
+
+impl<C: StreamCallback> Drop for StreamCallbackGuard<C> {
+    fn drop(&mut self) {
+        self.completion
+            .complete_on_drop(|err| self.callback.on_error(err));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::*;
+use parking_lot::Mutex;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::error;
+
+pub type CancelListener = Box<dyn FnOnce() + Send>;
+
+/// Backing implementation of a `CancellationToken`.
+pub trait CancellationSource: Send + Sync {
+    fn is_cancelled(&self) -> bool;
+    fn cancel(&self);
+    /// Registers `listener` to run once the token is cancelled, or runs it right away
+    /// if it already is.
+    fn on_cancel(&self, listener: CancelListener);
+    /// Time left until the deadline of the invocation, `None` if it has none.
+    fn remaining(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Returned by `Registry::invoke` to the caller and passed to the callee alongside the
+/// callback. The caller cancels, the callee observes.
+#[derive(Clone)]
+pub struct CancellationToken {
+    source: Arc<dyn CancellationSource>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::from_source(LocalCancellation::default())
+    }
+
+    pub fn with_deadline(deadline: Option<Instant>) -> Self {
+        Self::from_source(LocalCancellation {
+            deadline,
+            ..Default::default()
+        })
+    }
+
+    pub fn from_source<S: CancellationSource + 'static>(source: S) -> Self {
+        Self {
+            source: Arc::new(source),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.source.is_cancelled()
+    }
+
+    pub fn cancel(&self) {
+        self.source.cancel()
+    }
+
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, listener: F) {
+        self.source.on_cancel(Box::new(listener))
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.source.remaining()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.remaining().and_then(|d| Instant::now().checked_add(d))
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("is_cancelled", &self.is_cancelled())
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+struct LocalCancellation {
+    cancelled: AtomicBool,
+    listeners: Mutex<Vec<CancelListener>>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationSource for LocalCancellation {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let listeners = std::mem::take(&mut *self.listeners.lock());
+        for listener in listeners {
+            listener();
+        }
+    }
+
+    fn on_cancel(&self, listener: CancelListener) {
+        let mut listeners = self.listeners.lock();
+
+        if self.is_cancelled() {
+            drop(listeners);
+            listener();
+        } else {
+            listeners.push(listener);
+        }
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[repr(C)]
+pub struct NativeCancelListener {
+    instance: *mut (),
+    call: extern "C" fn(instance: *mut ()),
+    drop: extern "C" fn(instance: *mut ()),
+}
+
+unsafe impl Send for NativeCancelListener {}
+
+impl NativeCancelListener {
+    fn new(listener: CancelListener) -> Self {
+        Self {
+            instance: Box::into_raw(Box::new(listener)).cast(),
+            call: Self::call,
+            drop: Self::drop,
+        }
+    }
+
+    extern "C" fn call(instance: *mut ()) {
+        let listener = unsafe { Box::from_raw(instance as *mut CancelListener) };
+
+        if let Err(e) = catch_panic(listener) {
+            error!("panic in cancel listener: {}", e);
+        }
+    }
+
+    extern "C" fn drop(instance: *mut ()) {
+        let listener = unsafe { Box::from_raw(instance as *mut CancelListener) };
+
+        if let Err(e) = catch_panic(|| drop(listener)) {
+            error!("panic in cancel listener drop: {}", e);
+        }
+    }
+
+    fn into_fn(self) -> impl FnOnce() + Send {
+        move || {
+            let listener = std::mem::ManuallyDrop::new(self);
+            (listener.call)(listener.instance)
+        }
+    }
+}
+
+impl Drop for NativeCancelListener {
+    fn drop(&mut self) {
+        (self.drop)(self.instance)
+    }
+}
+
+#[repr(C)]
+pub struct NativeCancellationToken {
+    abi: NativeAbiHeader,
+    instance: *mut (),
+    is_cancelled: extern "C" fn(instance: *mut ()) -> u8,
+    cancel: extern "C" fn(instance: *mut ()),
+    on_cancel: extern "C" fn(instance: *mut (), listener: NativeCancelListener),
+    remaining_nanos: extern "C" fn(instance: *mut ()) -> u64,
+    clone: extern "C" fn(instance: *mut ()) -> NativeCancellationToken,
+    drop: extern "C" fn(instance: *mut ()),
+}
+
+unsafe impl Send for NativeCancellationToken {}
+unsafe impl Sync for NativeCancellationToken {}
+
+impl NativeCancellationToken {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            abi: NativeAbiHeader::new::<Self>(),
+            instance: Box::into_raw(Box::new(token)).cast(),
+            is_cancelled: Self::ffi_is_cancelled,
+            cancel: Self::ffi_cancel,
+            on_cancel: Self::ffi_on_cancel,
+            remaining_nanos: Self::ffi_remaining_nanos,
+            clone: Self::ffi_clone,
+            drop: Self::ffi_drop,
+        }
+    }
+
+    extern "C" fn ffi_is_cancelled(instance: *mut ()) -> u8 {
+        let token = unsafe { &*(instance as *const CancellationToken) };
+
+        catch_panic(|| token.is_cancelled() as u8).unwrap_or_else(|e| {
+            error!("panic in cancellation token is_cancelled: {}", e);
+            0
+        })
+    }
+
+    extern "C" fn ffi_cancel(instance: *mut ()) {
+        let token = unsafe { &*(instance as *const CancellationToken) };
+
+        if let Err(e) = catch_panic(|| token.cancel()) {
+            error!("panic in cancellation token cancel: {}", e);
+        }
+    }
+
+    extern "C" fn ffi_on_cancel(instance: *mut (), listener: NativeCancelListener) {
+        let token = unsafe { &*(instance as *const CancellationToken) };
+
+        if let Err(e) = catch_panic(|| token.on_cancel(listener.into_fn())) {
+            error!("panic in cancellation token on_cancel: {}", e);
+        }
+    }
+
+    extern "C" fn ffi_remaining_nanos(instance: *mut ()) -> u64 {
+        let token = unsafe { &*(instance as *const CancellationToken) };
+
+        catch_panic(|| duration_to_nanos(token.remaining())).unwrap_or_else(|e| {
+            error!("panic in cancellation token remaining: {}", e);
+            duration_to_nanos(None)
+        })
+    }
+
+    extern "C" fn ffi_clone(instance: *mut ()) -> NativeCancellationToken {
+        let token = unsafe { &*(instance as *const CancellationToken) };
+        Self::new(token.clone())
+    }
+
+    extern "C" fn ffi_drop(instance: *mut ()) {
+        let token = unsafe { Box::from_raw(instance as *mut CancellationToken) };
+
+        if let Err(e) = catch_panic(|| drop(token)) {
+            error!("panic in cancellation token drop: {}", e);
+        }
+    }
+}
+
+impl NativeAbi for NativeCancellationToken {
+    const NAME: &'static str = "NativeCancellationToken";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
+    }
+}
+
+impl CancellationSource for NativeCancellationToken {
+    fn is_cancelled(&self) -> bool {
+        (self.is_cancelled)(self.instance) != 0
+    }
+
+    fn cancel(&self) {
+        (self.cancel)(self.instance)
+    }
+
+    fn on_cancel(&self, listener: CancelListener) {
+        (self.on_cancel)(self.instance, NativeCancelListener::new(listener))
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        nanos_to_duration((self.remaining_nanos)(self.instance))
+    }
+}
+
+impl Clone for NativeCancellationToken {
+    fn clone(&self) -> Self {
+        (self.clone)(self.instance)
+    }
+}
+
+impl Drop for NativeCancellationToken {
+    fn drop(&mut self) {
+        (self.drop)(self.instance)
+    }
+}
+
+impl From<NativeCancellationToken> for CancellationToken {
+    fn from(token: NativeCancellationToken) -> Self {
+        if let Err(e) = token.check_abi() {
+            error!("ignoring cancellation token: {}", e);
+            std::mem::forget(token);
+            return Self::default();
+        }
+
+        Self::from_source(token)
+    }
+}
@@ -0,0 +1,161 @@
+use crate::errors::Error;
+use crate::*;
+use parking_lot::Mutex;
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+/// Encoding of typed invoke payloads, implemented for every type it can encode.
+pub trait Codec<T> {
+    fn encode(value: &T) -> Result<Vec<u8>, CodecError>;
+    /// `None` is decoded like the codec's representation of nothing, if it has one.
+    fn decode(data: Option<&[u8]>) -> Result<T, CodecError>;
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CodecError {
+    pub codec: &'static str,
+    pub message: String,
+}
+
+impl CodecError {
+    pub fn new<E: Display>(codec: &'static str, e: E) -> Self {
+        Self {
+            codec,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} codec: {}", self.codec, self.message)
+    }
+}
+
+impl StdError for CodecError {}
+
+/// Decode and encode failures are reported as `Error::InvalidPayload`.
+impl From<CodecError> for OwnedError {
+    fn from(e: CodecError) -> Self {
+        Self {
+            description: Some(e.to_string()),
+            ..Error::InvalidPayload.into()
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|e| CodecError::new("json", e))
+    }
+
+    fn decode(data: Option<&[u8]>) -> Result<T, CodecError> {
+        serde_json::from_slice(data.unwrap_or(b"null")).map_err(|e| CodecError::new("json", e))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for MsgPack {
+    fn encode(value: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec(value).map_err(|e| CodecError::new("msgpack", e))
+    }
+
+    fn decode(data: Option<&[u8]>) -> Result<T, CodecError> {
+        // 0xc0 is msgpack's nil
+        rmp_serde::from_slice(data.unwrap_or(&[0xc0])).map_err(|e| CodecError::new("msgpack", e))
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Bincode {
+    fn encode(value: &T) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|e| CodecError::new("bincode", e))
+    }
+
+    fn decode(data: Option<&[u8]>) -> Result<T, CodecError> {
+        bincode::deserialize(data.unwrap_or_default()).map_err(|e| CodecError::new("bincode", e))
+    }
+}
+
+#[cfg(feature = "protobuf")]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl<T: prost::Message + Default> Codec<T> for Protobuf {
+    fn encode(value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(data: Option<&[u8]>) -> Result<T, CodecError> {
+        T::decode(data.unwrap_or_default()).map_err(|e| CodecError::new("protobuf", e))
+    }
+}
+
+/// Handles an invocation with a typed function: decodes `data` with `C`, and completes
+/// `callback` with the encoded result, or the error it returned.
+pub fn handle_typed<C, Req, Resp, E, F>(data: Option<&[u8]>, callback: &dyn Callback, f: F)
+where
+    C: Codec<Req> + Codec<Resp>,
+    E: Into<OwnedError>,
+    F: FnOnce(Req) -> Result<Resp, E>,
+{
+    let result = <C as Codec<Req>>::decode(data)
+        .map_err(OwnedError::from)
+        .and_then(|req| f(req).map_err(Into::into))
+        .and_then(|resp| <C as Codec<Resp>>::encode(&resp).map_err(OwnedError::from));
+
+    match result {
+        Ok(data) => callback.on_success(CallbackSuccess { data: Some(&data) }),
+        Err(e) => callback.on_error(e.as_error()),
+    }
+}
+
+/// `Callback` decoding the response with `C` before handing it to a closure.
+pub struct TypedCallback<C, Resp, F> {
+    f: Mutex<Option<F>>,
+    _marker: PhantomData<fn() -> (C, Resp)>,
+}
+
+impl<C, Resp, F> TypedCallback<C, Resp, F>
+where
+    C: Codec<Resp>,
+    F: FnOnce(Result<Resp, OwnedError>) + Send,
+{
+    pub fn new(f: F) -> Self {
+        Self {
+            f: Mutex::new(Some(f)),
+            _marker: PhantomData,
+        }
+    }
+
+    fn complete(&self, result: Result<Resp, OwnedError>) {
+        if let Some(f) = self.f.lock().take() {
+            f(result);
+        }
+    }
+}
+
+impl<C, Resp, F> Callback for TypedCallback<C, Resp, F>
+where
+    C: Codec<Resp>,
+    F: FnOnce(Result<Resp, OwnedError>) + Send,
+{
+    fn on_success(&self, result: CallbackSuccess) {
+        self.complete(C::decode(result.data).map_err(Into::into));
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.complete(Err(err.into()));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::registry_event::native_string;
+use crate::*;
+
+/// A module another one needs registered before it starts.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Dependency {
+    pub package: String,
+    /// Semver requirement the version of `package` has to match, as in `^1.2`.
+    pub version: String,
+}
+
+impl Dependency {
+    pub fn new<P: Into<String>, V: Into<String>>(package: P, version: V) -> Self {
+        Self {
+            package: package.into(),
+            version: version.into(),
+        }
+    }
+}
+
+/// Borrows from the `Dependency` it was created from, like `NativeMethodDescriptor`.
+#[repr(C)]
+pub struct NativeDependency {
+    package: NativeByteSlice,
+    version: NativeByteSlice,
+}
+
+impl From<&Dependency> for NativeDependency {
+    fn from(dependency: &Dependency) -> Self {
+        Self {
+            package: NativeByteSlice::from(&dependency.package),
+            version: NativeByteSlice::from(&dependency.version),
+        }
+    }
+}
+
+impl From<NativeDependency> for Dependency {
+    fn from(dependency: NativeDependency) -> Self {
+        Self {
+            package: native_string(dependency.package),
+            version: native_string(dependency.version),
+        }
+    }
+}
+
+/// Receives the dependencies of a module one by one; `out` is the `Vec` they are collected in.
+pub type NativePushDependency = extern "C" fn(out: *mut (), dependency: NativeDependency);
+
+pub(crate) extern "C" fn push_dependency(out: *mut (), dependency: NativeDependency) {
+    let out = unsafe { &mut *(out as *mut Vec<Dependency>) };
+    out.push(dependency.into());
+}
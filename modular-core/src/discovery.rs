@@ -0,0 +1,135 @@
+use crate::registry_event::native_string;
+use crate::*;
+
+/// What runs a module.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum ModuleKind {
+    /// Compiled into the host, or a module whose backend didn't say.
+    #[default]
+    Native = 0,
+    Dll = 1,
+    Wasm = 2,
+}
+
+impl ModuleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModuleKind::Native => "native",
+            ModuleKind::Dll => "dll",
+            ModuleKind::Wasm => "wasm",
+        }
+    }
+}
+
+impl From<i32> for ModuleKind {
+    fn from(v: i32) -> Self {
+        match v {
+            1 => Self::Dll,
+            2 => Self::Wasm,
+            _ => Self::Native,
+        }
+    }
+}
+
+/// A module as listed by `Registry::find_modules` and `Registry::watch`, one per
+/// registered version of a package.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct RegisteredModule {
+    pub package: String,
+    pub version: String,
+    pub kind: ModuleKind,
+}
+
+/// Which packages `Registry::find_modules` lists.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ModuleQuery {
+    All,
+    Exact(String),
+    Prefix(String),
+    /// A pattern in which `*` matches any run of characters, as in `dll.*`.
+    Glob(String),
+}
+
+#[repr(i32)]
+enum NativeModuleQueryKind {
+    All = 0,
+    Exact = 1,
+    Prefix = 2,
+    Glob = 3,
+}
+
+/// Borrows from the `ModuleQuery` it was created from.
+#[repr(C)]
+pub struct NativeModuleQuery {
+    kind: i32,
+    pattern: NativeByteSlice,
+}
+
+impl From<&ModuleQuery> for NativeModuleQuery {
+    fn from(query: &ModuleQuery) -> Self {
+        let (kind, pattern) = match query {
+            ModuleQuery::All => (NativeModuleQueryKind::All, None),
+            ModuleQuery::Exact(p) => (NativeModuleQueryKind::Exact, Some(p)),
+            ModuleQuery::Prefix(p) => (NativeModuleQueryKind::Prefix, Some(p)),
+            ModuleQuery::Glob(p) => (NativeModuleQueryKind::Glob, Some(p)),
+        };
+
+        Self {
+            kind: kind as i32,
+            pattern: pattern.map(NativeByteSlice::from).unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<NativeModuleQuery> for ModuleQuery {
+    type Error = i32;
+
+    fn try_from(query: NativeModuleQuery) -> Result<Self, Self::Error> {
+        let pattern = native_string(query.pattern);
+
+        match query.kind {
+            0 => Ok(Self::All),
+            1 => Ok(Self::Exact(pattern)),
+            2 => Ok(Self::Prefix(pattern)),
+            3 => Ok(Self::Glob(pattern)),
+            v => Err(v),
+        }
+    }
+}
+
+/// Borrows from the `RegisteredModule` it was created from.
+#[repr(C)]
+pub struct NativeRegisteredModule {
+    package: NativeByteSlice,
+    version: NativeByteSlice,
+    kind: i32,
+}
+
+impl From<&RegisteredModule> for NativeRegisteredModule {
+    fn from(module: &RegisteredModule) -> Self {
+        Self {
+            package: NativeByteSlice::from(&module.package),
+            version: NativeByteSlice::from(&module.version),
+            kind: module.kind as i32,
+        }
+    }
+}
+
+impl From<NativeRegisteredModule> for RegisteredModule {
+    fn from(module: NativeRegisteredModule) -> Self {
+        Self {
+            package: native_string(module.package),
+            version: native_string(module.version),
+            kind: module.kind.into(),
+        }
+    }
+}
+
+/// Receives registered modules one by one; `out` is the `Vec` they are collected in.
+pub type NativePushModule = extern "C" fn(out: *mut (), module: NativeRegisteredModule);
+
+pub(crate) extern "C" fn push_module(out: *mut (), module: NativeRegisteredModule) {
+    let out = unsafe { &mut *(out as *mut Vec<RegisteredModule>) };
+    out.push(module.into());
+}
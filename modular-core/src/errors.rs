@@ -1,11 +1,34 @@
 #[repr(i32)]
-#[derive(Default, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
 pub enum Error {
     #[default]
     NoError = 0,
     RegistryAlreadyRunning = i32::MIN,
     ModuleNotFound = i32::MIN + 1,
     FfiInvalidMethodName = i32::MIN + 2,
+    Panicked = i32::MIN + 3,
+    CallbackDropped = i32::MIN + 4,
+    Cancelled = i32::MIN + 5,
+    Timeout = i32::MIN + 6,
+    MethodNotFound = i32::MIN + 7,
+    InvalidPayload = i32::MIN + 8,
+    UnmetDependency = i32::MIN + 9,
+    DependencyCycle = i32::MIN + 10,
+    ModuleAlreadyRegistered = i32::MIN + 11,
+    InvalidModule = i32::MIN + 12,
+    PermissionDenied = i32::MIN + 13,
+    RestoreFailed = i32::MIN + 14,
+    ConfigureFailed = i32::MIN + 15,
+}
+
+impl Error {
+    /// `Ok` for `NoError`, the error otherwise.
+    pub fn into_result(self) -> Result<(), Error> {
+        match self {
+            Self::NoError => Ok(()),
+            e => Err(e),
+        }
+    }
 }
 
 impl AsRef<str> for Error {
@@ -14,6 +37,19 @@ impl AsRef<str> for Error {
             Self::RegistryAlreadyRunning => "Registry already running",
             Self::ModuleNotFound => "Module not found",
             Self::FfiInvalidMethodName => "Invalid method name",
+            Self::Panicked => "Panicked",
+            Self::CallbackDropped => "Callback dropped without completion",
+            Self::Cancelled => "Cancelled",
+            Self::Timeout => "Deadline exceeded",
+            Self::MethodNotFound => "Method not found",
+            Self::InvalidPayload => "Invalid payload",
+            Self::UnmetDependency => "Unmet dependency",
+            Self::DependencyCycle => "Dependency cycle",
+            Self::ModuleAlreadyRegistered => "Module already registered",
+            Self::InvalidModule => "Invalid module",
+            Self::PermissionDenied => "Permission denied",
+            Self::RestoreFailed => "Restore failed",
+            Self::ConfigureFailed => "Configure failed",
             _ => "",
         }
     }
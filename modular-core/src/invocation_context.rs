@@ -0,0 +1,219 @@
+use crate::*;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+/// Arbitrary string metadata travelling with an invocation, as in `trace-id` or `tenant`.
+pub type Headers = BTreeMap<String, String>;
+
+thread_local! {
+    static CURRENT_INVOCATION: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Id of the invocation currently being dispatched on this thread, if any.
+pub fn current_invocation() -> Option<u64> {
+    CURRENT_INVOCATION.with(|i| i.get())
+}
+
+/// Runs `f` with `id` as the current invocation, so that registry calls made
+/// synchronously from within `f` get it as their parent.
+pub fn with_current_invocation<R>(id: u64, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<u64>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_INVOCATION.with(|i| i.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT_INVOCATION.with(|i| i.replace(Some(id))));
+    f()
+}
+
+/// The module an invocation was made by.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Caller {
+    pub package: String,
+    pub version: String,
+}
+
+/// Who is invoking a module and on behalf of which other invocation, passed to
+/// `Module::invoke` with every call.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct InvocationContext {
+    /// Unique among the invocations of the registry dispatching it.
+    pub id: u64,
+    /// The invocation this one was made from, if any.
+    pub parent: Option<u64>,
+    /// `None` if the host made the invocation rather than a module.
+    pub caller: Option<Caller>,
+    pub headers: Headers,
+}
+
+impl InvocationContext {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// Flat encoding of the context, for passing it into a wasm guest.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(&self.id.to_le_bytes());
+        out.extend_from_slice(&self.parent.unwrap_or(NO_INVOCATION).to_le_bytes());
+
+        match &self.caller {
+            Some(caller) => {
+                out.push(1);
+                put_str(&mut out, &caller.package);
+                put_str(&mut out, &caller.version);
+            }
+            None => out.push(0),
+        }
+
+        out.extend_from_slice(&encode_headers(&self.headers));
+        out
+    }
+
+    /// Decodes what `encode` produced, `None` if `bytes` is malformed.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+
+        let id = reader.u64()?;
+        let parent = reader.u64()?;
+        let caller = match reader.take(1)?[0] {
+            0 => None,
+            _ => Some(Caller {
+                package: reader.string()?,
+                version: reader.string()?,
+            }),
+        };
+
+        Some(Self {
+            id,
+            parent: (parent != NO_INVOCATION).then_some(parent),
+            caller,
+            headers: reader.headers()?,
+        })
+    }
+}
+
+/// Invocation ids start at 1, so 0 stands for no invocation across the FFI boundary.
+pub(crate) const NO_INVOCATION: u64 = 0;
+
+/// Flat encoding of `headers`: their count followed by each name and value, all
+/// length-prefixed.
+pub fn encode_headers(headers: &Headers) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend_from_slice(&(headers.len() as u32).to_le_bytes());
+
+    for (name, value) in headers {
+        put_str(&mut out, name);
+        put_str(&mut out, value);
+    }
+
+    out
+}
+
+/// Decodes what `encode_headers` produced, `None` if `bytes` is malformed.
+pub fn decode_headers(bytes: &[u8]) -> Option<Headers> {
+    Reader(bytes).headers()
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+    }
+
+    fn headers(&mut self) -> Option<Headers> {
+        let count = self.u32()?;
+        let mut headers = Headers::new();
+
+        for _ in 0..count {
+            headers.insert(self.string()?, self.string()?);
+        }
+
+        Some(headers)
+    }
+}
+
+/// Borrows from the `InvocationContext` it was created from and from the headers encoded
+/// with `encode_headers`, like `NativeMethodDescriptor`.
+#[repr(C)]
+pub struct NativeInvocationContext {
+    id: u64,
+    parent_id: u64,
+    /// Null if the host made the invocation.
+    caller_package: NativeByteSlice,
+    caller_version: NativeByteSlice,
+    headers: NativeByteSlice,
+}
+
+impl NativeInvocationContext {
+    pub fn new(context: &InvocationContext, headers: &[u8]) -> Self {
+        let (caller_package, caller_version) = match &context.caller {
+            Some(caller) => (
+                NativeByteSlice::from(&caller.package),
+                NativeByteSlice::from(&caller.version),
+            ),
+            None => Default::default(),
+        };
+
+        Self {
+            id: context.id,
+            parent_id: context.parent.unwrap_or(NO_INVOCATION),
+            caller_package,
+            caller_version,
+            headers: NativeByteSlice::from(headers),
+        }
+    }
+}
+
+impl From<NativeInvocationContext> for InvocationContext {
+    fn from(context: NativeInvocationContext) -> Self {
+        let string = |s: &[u8]| String::from_utf8_lossy(s).into_owned();
+
+        let caller = Option::<&[u8]>::from(context.caller_package).map(|package| Caller {
+            package: string(package),
+            version: Option::<&[u8]>::from(context.caller_version)
+                .map(string)
+                .unwrap_or_default(),
+        });
+
+        Self {
+            id: context.id,
+            parent: (context.parent_id != NO_INVOCATION).then_some(context.parent_id),
+            caller,
+            headers: Option::<&[u8]>::from(context.headers)
+                .and_then(decode_headers)
+                .unwrap_or_default(),
+        }
+    }
+}
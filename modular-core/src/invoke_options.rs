@@ -0,0 +1,127 @@
+use crate::*;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static CURRENT_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Deadline of the invocation currently being dispatched on this thread, if any.
+pub fn current_deadline() -> Option<Instant> {
+    CURRENT_DEADLINE.with(|d| d.get())
+}
+
+/// Runs `f` with `deadline` as the current deadline, so that registry calls made
+/// synchronously from within `f` inherit it.
+pub fn with_current_deadline<R>(deadline: Option<Instant>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Instant>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_DEADLINE.with(|d| d.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT_DEADLINE.with(|d| d.replace(deadline)));
+    f()
+}
+
+/// Per-call settings for `Registry::invoke_with`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct InvokeOptions {
+    pub deadline: Option<Instant>,
+    /// The invocation this call is made on behalf of. Defaults to the current invocation
+    /// of the calling thread.
+    pub parent: Option<u64>,
+    pub headers: Headers,
+}
+
+impl InvokeOptions {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Default::default()
+        }
+    }
+
+    /// Options carrying the deadline of `token`, for calls made on behalf of an invocation
+    /// outside of its dispatching thread.
+    pub fn inherit(token: &CancellationToken) -> Self {
+        Self {
+            deadline: token.deadline(),
+            ..Default::default()
+        }
+    }
+
+    /// Makes the call a child of `context`, for calls made on behalf of an invocation
+    /// outside of its dispatching thread.
+    pub fn child_of(mut self, context: &InvocationContext) -> Self {
+        self.parent = Some(context.id);
+        self
+    }
+
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// The earliest of the own deadline and the one inherited from the current invocation.
+    pub fn effective_deadline(&self) -> Option<Instant> {
+        match (self.deadline, current_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+const NO_DEADLINE: u64 = u64::MAX;
+
+pub(crate) fn duration_to_nanos(duration: Option<Duration>) -> u64 {
+    duration.map_or(NO_DEADLINE, |d| {
+        d.as_nanos().min(NO_DEADLINE as u128 - 1) as u64
+    })
+}
+
+pub(crate) fn nanos_to_duration(nanos: u64) -> Option<Duration> {
+    (nanos != NO_DEADLINE).then(|| Duration::from_nanos(nanos))
+}
+
+/// `Instant`s are not meaningful across the FFI boundary, so the deadline travels
+/// as the time remaining until it. Borrows the headers encoded with `encode_headers`.
+#[repr(C)]
+pub struct NativeInvokeOptions {
+    remaining_nanos: u64,
+    parent_id: u64,
+    headers: NativeByteSlice,
+}
+
+impl NativeInvokeOptions {
+    pub fn new(options: &InvokeOptions, headers: &[u8]) -> Self {
+        let remaining = options
+            .deadline
+            .map(|d| d.saturating_duration_since(Instant::now()));
+
+        Self {
+            remaining_nanos: duration_to_nanos(remaining),
+            parent_id: options.parent.unwrap_or(NO_INVOCATION),
+            headers: NativeByteSlice::from(headers),
+        }
+    }
+}
+
+impl From<NativeInvokeOptions> for InvokeOptions {
+    fn from(options: NativeInvokeOptions) -> Self {
+        Self {
+            deadline: nanos_to_duration(options.remaining_nanos)
+                .and_then(|d| Instant::now().checked_add(d)),
+            parent: (options.parent_id != NO_INVOCATION).then_some(options.parent_id),
+            headers: Option::<&[u8]>::from(options.headers)
+                .and_then(decode_headers)
+                .unwrap_or_default(),
+        }
+    }
+}
@@ -1,16 +1,47 @@
 #![allow(dead_code)]
 
+mod abi;
+mod async_registry;
 mod callback;
+mod callback_guard;
+mod cancellation;
+mod codec;
+mod dependency;
+mod discovery;
 mod errors;
+mod invocation_context;
+mod invoke_options;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod macro_support;
+mod method_descriptor;
 mod module;
 mod native_byte_slice;
+mod panic;
 mod registry;
+mod registry_event;
+mod stream_callback;
 
+pub use abi::*;
+pub use async_registry::*;
 pub use callback::*;
+pub use callback_guard::*;
+pub use cancellation::*;
+pub use codec::*;
+pub use dependency::*;
+pub use discovery::*;
 pub use errors::*;
+pub use invocation_context::*;
+pub use invoke_options::*;
+pub use method_descriptor::*;
+#[cfg(feature = "macros")]
+pub use modular_macros::module;
 pub use module::*;
 pub use native_byte_slice::*;
+pub use panic::catch_panic;
 pub use registry::*;
+pub use registry_event::*;
+pub use stream_callback::*;
 
 #[macro_export]
 macro_rules! get_str {
@@ -0,0 +1,94 @@
+//! Used by the code `#[module]` expands to, not meant to be called directly.
+
+use crate::errors::Error;
+use crate::*;
+
+fn error(e: Error, description: String) -> OwnedError {
+    OwnedError {
+        description: Some(description),
+        ..OwnedError::from(e)
+    }
+}
+
+pub fn decode<C: Codec<T>, T>(method: &str, data: Option<&[u8]>) -> Result<T, OwnedError> {
+    C::decode(data).map_err(|e| {
+        error(
+            Error::InvalidPayload,
+            format!("invalid payload for {:?}: {}", method, e),
+        )
+    })
+}
+
+pub fn respond<C: Codec<T>, T>(callback: &dyn Callback, method: &str, value: &T) {
+    match C::encode(value) {
+        Ok(data) => callback.on_success(CallbackSuccess { data: Some(&data) }),
+        Err(e) => callback.on_error(
+            error(
+                Error::InvalidPayload,
+                format!("failed to encode result of {:?}: {}", method, e),
+            )
+            .as_error(),
+        ),
+    }
+}
+
+pub fn respond_unit(callback: &dyn Callback) {
+    callback.on_success(CallbackSuccess { data: None })
+}
+
+pub fn respond_result<C: Codec<T>, T, E: Into<OwnedError>>(
+    callback: &dyn Callback,
+    method: &str,
+    result: Result<T, E>,
+) {
+    match result {
+        Ok(value) => respond::<C, T>(callback, method, &value),
+        Err(e) => callback.on_error(e.into().as_error()),
+    }
+}
+
+pub fn method_not_found(callback: &dyn Callback, package: &str, method: &str) {
+    callback.on_error(
+        error(
+            Error::MethodNotFound,
+            format!("module {:?} has no method {:?}", package, method),
+        )
+        .as_error(),
+    )
+}
+
+/// Declares `$name` as the codec of a `#[module]` not given one: `Json`, which needs the
+/// `json` feature.
+#[cfg(feature = "json")]
+#[macro_export]
+macro_rules! __default_codec {
+    ($name:ident) => {
+        type $name = $crate::Json;
+    };
+}
+
+#[cfg(not(feature = "json"))]
+#[macro_export]
+macro_rules! __default_codec {
+    ($name:ident) => {
+        ::std::compile_error!(
+            "#[module] needs `codec = ...` without the `json` feature of modular-core"
+        );
+        type $name = $crate::macro_support::NoCodec;
+    };
+}
+
+/// Stands in for the codec of a `#[module]` missing one, so that only that is reported.
+#[cfg(not(feature = "json"))]
+pub enum NoCodec {}
+
+#[cfg(not(feature = "json"))]
+impl<T> Codec<T> for NoCodec {
+    fn encode(_: &T) -> Result<Vec<u8>, CodecError> {
+        unreachable!()
+    }
+
+    fn decode(_: Option<&[u8]>) -> Result<T, CodecError> {
+        unreachable!()
+    }
+}
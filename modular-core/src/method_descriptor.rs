@@ -0,0 +1,85 @@
+use crate::*;
+
+/// Describes one method a module accepts, for tools and other modules to discover.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MethodDescriptor {
+    pub name: String,
+    pub description: Option<String>,
+    /// Identifier of the request payload schema, named however the module's encoding does.
+    pub request_schema: Option<String>,
+    pub response_schema: Option<String>,
+    /// Whether the method is meant to be called with `invoke_stream`.
+    pub streaming: bool,
+    /// Whether repeating a call with the same payload has no further effect.
+    pub idempotent: bool,
+}
+
+impl MethodDescriptor {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+const STREAMING: u8 = 1;
+const IDEMPOTENT: u8 = 1 << 1;
+
+/// Borrows from the `MethodDescriptor` it was created from, so it is only valid for the
+/// duration of the call it is passed to.
+#[repr(C)]
+pub struct NativeMethodDescriptor {
+    name: NativeByteSlice,
+    description: NativeByteSlice,
+    request_schema: NativeByteSlice,
+    response_schema: NativeByteSlice,
+    flags: u8,
+}
+
+impl From<&MethodDescriptor> for NativeMethodDescriptor {
+    fn from(method: &MethodDescriptor) -> Self {
+        let slice = |s: &Option<String>| s.as_ref().map(NativeByteSlice::from).unwrap_or_default();
+
+        let mut flags = 0;
+        if method.streaming {
+            flags |= STREAMING;
+        }
+        if method.idempotent {
+            flags |= IDEMPOTENT;
+        }
+
+        Self {
+            name: NativeByteSlice::from(&method.name),
+            description: slice(&method.description),
+            request_schema: slice(&method.request_schema),
+            response_schema: slice(&method.response_schema),
+            flags,
+        }
+    }
+}
+
+impl From<NativeMethodDescriptor> for MethodDescriptor {
+    fn from(method: NativeMethodDescriptor) -> Self {
+        let string = |s: NativeByteSlice| {
+            Option::<&[u8]>::from(s).map(|s| String::from_utf8_lossy(s).into_owned())
+        };
+
+        Self {
+            name: string(method.name).unwrap_or_default(),
+            description: string(method.description),
+            request_schema: string(method.request_schema),
+            response_schema: string(method.response_schema),
+            streaming: method.flags & STREAMING != 0,
+            idempotent: method.flags & IDEMPOTENT != 0,
+        }
+    }
+}
+
+/// Receives the descriptors of a module one by one; `out` is the `Vec` they are collected in.
+pub type NativePushMethod = extern "C" fn(out: *mut (), method: NativeMethodDescriptor);
+
+pub(crate) extern "C" fn push_method(out: *mut (), method: NativeMethodDescriptor) {
+    let out = unsafe { &mut *(out as *mut Vec<MethodDescriptor>) };
+    out.push(method.into());
+}
@@ -1,12 +1,95 @@
 use crate::errors::Error;
+use crate::panic::invoke_catching_panic;
 use crate::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::error;
 
 pub trait Module: Send + Sync {
     fn package(&self) -> &str;
     fn version(&self) -> &str;
 
+    fn kind(&self) -> ModuleKind {
+        ModuleKind::Native
+    }
+
+    /// Methods the module accepts. Empty if the module doesn't describe them,
+    /// which doesn't mean it accepts none.
+    fn methods(&self) -> Vec<MethodDescriptor> {
+        vec![]
+    }
+
+    /// Modules that have to be registered, in a matching version, for this one to start.
+    /// The registry starts them first.
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![]
+    }
+
+    /// Called once when the registry starts, before `start`.
+    fn init(&self) {}
+
+    /// Called after `init`, right before `run` is spawned on its own thread.
+    fn start(&self) {}
+
     fn run(&self);
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>);
+
+    /// Asks `run` to return. Called from another thread, possibly while `run` is executing.
+    fn stop(&self) {}
+
+    /// Called once `run` has returned, to release what `init` acquired.
+    fn shutdown(&self) {}
+
+    /// State to hand over to the module replacing this one in `Modular::swap_module`,
+    /// `None` if there is none. Called once no invocation of the module is in flight, and
+    /// not at all if some still are when the drain timeout of the registry passes.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Takes over the state `snapshot` returned from the module this one replaces. Called
+    /// before `init`.
+    fn restore(&self, _snapshot: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Applies the configuration the host was given for the module, as JSON. Called before
+    /// `init`.
+    fn configure(&self, _config: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether a lifecycle hook failed; such a module should not be relied upon anymore.
+    fn is_failed(&self) -> bool {
+        false
+    }
+
+    /// `context` tells who is invoking the module and on behalf of which invocation.
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    );
+
+    /// Streaming variant of `invoke`. By default the single `invoke` response
+    /// is delivered as one message followed by `on_complete`.
+    fn invoke_stream(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.invoke(
+            method,
+            data,
+            Box::new(SingleMessageStream(callback)),
+            token,
+            context,
+        )
+    }
 }
 
 impl Module for Box<dyn Module> {
@@ -18,53 +101,205 @@ impl Module for Box<dyn Module> {
         self.as_ref().version()
     }
 
+    fn kind(&self) -> ModuleKind {
+        self.as_ref().kind()
+    }
+
+    fn methods(&self) -> Vec<MethodDescriptor> {
+        self.as_ref().methods()
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        self.as_ref().dependencies()
+    }
+
+    fn init(&self) {
+        self.as_ref().init()
+    }
+
+    fn start(&self) {
+        self.as_ref().start()
+    }
+
     fn run(&self) {
         self.as_ref().run()
     }
 
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
-        self.as_ref().invoke(method, data, callback)
+    fn stop(&self) {
+        self.as_ref().stop()
+    }
+
+    fn shutdown(&self) {
+        self.as_ref().shutdown()
+    }
+
+    fn is_failed(&self) -> bool {
+        self.as_ref().is_failed()
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        self.as_ref().snapshot()
+    }
+
+    fn restore(&self, snapshot: &[u8]) -> Result<(), String> {
+        self.as_ref().restore(snapshot)
+    }
+
+    fn configure(&self, config: &[u8]) -> Result<(), String> {
+        self.as_ref().configure(config)
+    }
+
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.as_ref().invoke(method, data, callback, token, context)
+    }
+
+    fn invoke_stream(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.as_ref()
+            .invoke_stream(method, data, callback, token, context)
     }
 }
 
 #[repr(C)]
 pub struct NativeModule {
+    abi: NativeAbiHeader,
     instance: *mut (),
     package_fn: extern "C" fn(instance: *mut ()) -> NativeByteSlice,
     version_fn: extern "C" fn(instance: *mut ()) -> NativeByteSlice,
+    methods_fn: extern "C" fn(instance: *mut (), out: *mut (), push: NativePushMethod),
+    dependencies_fn: extern "C" fn(instance: *mut (), out: *mut (), push: NativePushDependency),
     invoke_fn: extern "C" fn(
         instance: *mut (),
         method: NativeByteSlice,
         data: NativeByteSlice,
         callback: NativeCallback,
+        token: NativeCancellationToken,
+        context: NativeInvocationContext,
+    ),
+    invoke_stream_fn: extern "C" fn(
+        instance: *mut (),
+        method: NativeByteSlice,
+        data: NativeByteSlice,
+        callback: NativeStreamCallback,
+        token: NativeCancellationToken,
+        context: NativeInvocationContext,
     ),
-    run_fn: Option<extern "C" fn(instance: *mut ())>,
+    init_fn: extern "C" fn(instance: *mut ()) -> Error,
+    start_fn: extern "C" fn(instance: *mut ()) -> Error,
+    run_fn: extern "C" fn(instance: *mut ()) -> Error,
+    stop_fn: extern "C" fn(instance: *mut ()) -> Error,
+    shutdown_fn: extern "C" fn(instance: *mut ()) -> Error,
+    snapshot_fn: extern "C" fn(instance: *mut (), out: *mut (), push: NativePushBytes) -> bool,
+    /// Pushes the reason into `out` unless it returns `Error::NoError`, as does `configure_fn`.
+    restore_fn: extern "C" fn(
+        instance: *mut (),
+        snapshot: NativeByteSlice,
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> Error,
+    configure_fn: extern "C" fn(
+        instance: *mut (),
+        config: NativeByteSlice,
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> Error,
     drop_fn: extern "C" fn(instance: *mut ()),
+    failed: AtomicBool,
 }
 
 unsafe impl Send for NativeModule {}
 unsafe impl Sync for NativeModule {}
 
 impl NativeModule {
+    /// Header of the layouts this side was built with, for the `ABI_SYMBOL` of a dll module.
+    pub const fn abi() -> NativeAbiHeader {
+        NativeAbiHeader::new::<Self>()
+    }
+
     pub fn new<T: Module + 'static>(module: T) -> Self {
         Self {
+            abi: Self::abi(),
             instance: Box::into_raw(Box::new(module)).cast(),
             package_fn: Self::package_fn::<T>,
             version_fn: Self::version_fn::<T>,
+            methods_fn: Self::methods_fn::<T>,
+            dependencies_fn: Self::dependencies_fn::<T>,
             invoke_fn: Self::invoke_fn::<T>,
-            run_fn: Some(Self::run_fn::<T>),
+            invoke_stream_fn: Self::invoke_stream_fn::<T>,
+            init_fn: Self::init_fn::<T>,
+            start_fn: Self::start_fn::<T>,
+            run_fn: Self::run_fn::<T>,
+            stop_fn: Self::stop_fn::<T>,
+            shutdown_fn: Self::shutdown_fn::<T>,
+            snapshot_fn: Self::snapshot_fn::<T>,
+            restore_fn: Self::restore_fn::<T>,
+            configure_fn: Self::configure_fn::<T>,
             drop_fn: Self::drop_fn::<T>,
+            failed: AtomicBool::new(false),
         }
     }
 
     extern "C" fn package_fn<T: Module>(instance: *mut ()) -> NativeByteSlice {
         let module = unsafe { &*(instance as *const T) };
-        module.package().into()
+
+        catch_panic(|| module.package().into()).unwrap_or_else(|e| {
+            error!("panic in module package: {}", e);
+            NativeByteSlice::default()
+        })
     }
 
     extern "C" fn version_fn<T: Module>(instance: *mut ()) -> NativeByteSlice {
         let module = unsafe { &*(instance as *const T) };
-        module.version().into()
+
+        catch_panic(|| module.version().into()).unwrap_or_else(|e| {
+            error!("panic in module version: {}", e);
+            NativeByteSlice::default()
+        })
+    }
+
+    extern "C" fn methods_fn<T: Module>(instance: *mut (), out: *mut (), push: NativePushMethod) {
+        let module = unsafe { &*(instance as *const T) };
+
+        let result = catch_panic(|| {
+            for method in module.methods() {
+                push(out, (&method).into());
+            }
+        });
+
+        if let Err(e) = result {
+            error!("panic in module methods: {}", e);
+        }
+    }
+
+    extern "C" fn dependencies_fn<T: Module>(
+        instance: *mut (),
+        out: *mut (),
+        push: NativePushDependency,
+    ) {
+        let module = unsafe { &*(instance as *const T) };
+
+        let result = catch_panic(|| {
+            for dependency in module.dependencies() {
+                push(out, (&dependency).into());
+            }
+        });
+
+        if let Err(e) = result {
+            error!("panic in module dependencies: {}", e);
+        }
     }
 
     extern "C" fn invoke_fn<T: Module>(
@@ -72,38 +307,225 @@ impl NativeModule {
         method: NativeByteSlice,
         data: NativeByteSlice,
         callback: NativeCallback,
+        token: NativeCancellationToken,
+        context: NativeInvocationContext,
     ) {
         let module = unsafe { &*(instance as *const T) };
-        let method = Option::<&[u8]>::from(method).and_then(|s| std::str::from_utf8(s).ok());
-
-        let method = match method {
-            Some(v) => v,
-            None => {
-                Callback::on_error(
-                    &callback,
-                    CallbackError {
-                        code: Error::FfiInvalidMethodName as i32,
-                        err_name: Error::FfiInvalidMethodName.as_ref().into(),
-                        description: "empty or non-valid (not utf8) method name".into(),
-                        data: None,
-                    },
-                );
-                return;
-            }
+        let token = CancellationToken::from(token);
+
+        if let Err(e) = callback.check_abi() {
+            error!("rejecting module invoke: {}", e);
+            std::mem::forget(callback);
+            return;
+        }
+
+        let method = match Self::method_name(method) {
+            Ok(v) => v,
+            Err(e) => return Callback::on_error(&callback, e),
+        };
+
+        let data = Option::<&[u8]>::from(data);
+
+        let context = InvocationContext::from(context);
+
+        invoke_catching_panic("module invoke", callback, |callback| {
+            with_current_invocation(context.id, || {
+                module.invoke(method, data, callback, token, context)
+            })
+        });
+    }
+
+    extern "C" fn invoke_stream_fn<T: Module>(
+        instance: *mut (),
+        method: NativeByteSlice,
+        data: NativeByteSlice,
+        callback: NativeStreamCallback,
+        token: NativeCancellationToken,
+        context: NativeInvocationContext,
+    ) {
+        let module = unsafe { &*(instance as *const T) };
+        let token = CancellationToken::from(token);
+
+        if let Err(e) = callback.check_abi() {
+            error!("rejecting module invoke_stream: {}", e);
+            std::mem::forget(callback);
+            return;
+        }
+
+        let method = match Self::method_name(method) {
+            Ok(v) => v,
+            Err(e) => return StreamCallback::on_error(&callback, e),
         };
 
         let data = Option::<&[u8]>::from(data);
 
-        module.invoke(method, data, Box::new(callback));
+        let context = InvocationContext::from(context);
+
+        invoke_catching_panic("module invoke_stream", callback, |callback| {
+            with_current_invocation(context.id, || {
+                module.invoke_stream(method, data, callback, token, context)
+            })
+        });
+    }
+
+    fn method_name<'a>(method: NativeByteSlice) -> Result<&'a str, CallbackError<'static>> {
+        Option::<&[u8]>::from(method)
+            .and_then(|s| std::str::from_utf8(s).ok())
+            .ok_or(CallbackError {
+                code: Error::FfiInvalidMethodName as i32,
+                err_name: Error::FfiInvalidMethodName.as_ref().into(),
+                description: "empty or non-valid (not utf8) method name".into(),
+                data: None,
+            })
+    }
+
+    fn lifecycle_fn<T: Module>(instance: *mut (), hook: &str, f: fn(&T)) -> Error {
+        let module = unsafe { &*(instance as *const T) };
+
+        match catch_panic(|| f(module)) {
+            Ok(()) => Error::NoError,
+            Err(e) => {
+                error!("panic in module {}: {}", hook, e);
+                Error::Panicked
+            }
+        }
+    }
+
+    extern "C" fn init_fn<T: Module>(instance: *mut ()) -> Error {
+        Self::lifecycle_fn::<T>(instance, "init", T::init)
+    }
+
+    extern "C" fn start_fn<T: Module>(instance: *mut ()) -> Error {
+        Self::lifecycle_fn::<T>(instance, "start", T::start)
+    }
+
+    extern "C" fn run_fn<T: Module>(instance: *mut ()) -> Error {
+        Self::lifecycle_fn::<T>(instance, "run", T::run)
+    }
+
+    extern "C" fn stop_fn<T: Module>(instance: *mut ()) -> Error {
+        Self::lifecycle_fn::<T>(instance, "stop", T::stop)
+    }
+
+    extern "C" fn shutdown_fn<T: Module>(instance: *mut ()) -> Error {
+        Self::lifecycle_fn::<T>(instance, "shutdown", T::shutdown)
+    }
+
+    extern "C" fn snapshot_fn<T: Module>(
+        instance: *mut (),
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> bool {
+        let module = unsafe { &*(instance as *const T) };
+
+        match catch_panic(|| module.snapshot()) {
+            Ok(Some(snapshot)) => {
+                push(out, (&snapshot).into());
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                error!("panic in module snapshot: {}", e);
+                false
+            }
+        }
     }
 
-    extern "C" fn run_fn<T: Module>(instance: *mut ()) {
+    extern "C" fn restore_fn<T: Module>(
+        instance: *mut (),
+        snapshot: NativeByteSlice,
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> Error {
         let module = unsafe { &*(instance as *const T) };
-        module.run();
+        let snapshot = Option::<&[u8]>::from(snapshot).unwrap_or_default();
+
+        Self::hook_result(
+            "restore",
+            catch_panic(|| module.restore(snapshot)),
+            Error::RestoreFailed,
+            out,
+            push,
+        )
+    }
+
+    extern "C" fn configure_fn<T: Module>(
+        instance: *mut (),
+        config: NativeByteSlice,
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> Error {
+        let module = unsafe { &*(instance as *const T) };
+        let config = Option::<&[u8]>::from(config).unwrap_or_default();
+
+        Self::hook_result(
+            "configure",
+            catch_panic(|| module.configure(config)),
+            Error::ConfigureFailed,
+            out,
+            push,
+        )
+    }
+
+    /// Maps the outcome of a hook failing with a reason to the error `failed` or
+    /// `Error::Panicked`, pushing the reason into `out`.
+    fn hook_result(
+        hook: &str,
+        result: Result<Result<(), String>, String>,
+        failed: Error,
+        out: *mut (),
+        push: NativePushBytes,
+    ) -> Error {
+        let (error, reason) = match result {
+            Ok(Ok(())) => return Error::NoError,
+            Ok(Err(reason)) => (failed, reason),
+            Err(e) => (Error::Panicked, format!("panic in module {}: {}", hook, e)),
+        };
+
+        push(out, (&reason).into());
+        error
+    }
+
+    /// Runs a hook on the module side, marking the module failed if it doesn't succeed.
+    fn lifecycle(&self, hook: &str, f: extern "C" fn(*mut ()) -> Error) {
+        if f(self.instance) != Error::NoError {
+            error!("module {:?} failed in {}", self.package(), hook);
+            self.failed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Calls `restore_fn` or `configure_fn` with `bytes`, returning the reason if it fails.
+    fn call_with_reason(
+        &self,
+        f: extern "C" fn(*mut (), NativeByteSlice, *mut (), NativePushBytes) -> Error,
+        bytes: &[u8],
+    ) -> Result<(), String> {
+        let mut reason = Vec::<u8>::new();
+        match f(
+            self.instance,
+            bytes.into(),
+            &mut reason as *mut _ as *mut (),
+            push_bytes,
+        ) {
+            Error::NoError => Ok(()),
+            _ => Err(String::from_utf8_lossy(&reason).into_owned()),
+        }
     }
 
     extern "C" fn drop_fn<T: Module>(instance: *mut ()) {
-        let _ = unsafe { Box::from_raw(instance as *mut T) };
+        let module = unsafe { Box::from_raw(instance as *mut T) };
+
+        if let Err(e) = catch_panic(|| drop(module)) {
+            error!("panic in module drop: {}", e);
+        }
+    }
+}
+
+impl NativeAbi for NativeModule {
+    const NAME: &'static str = "NativeModule";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
     }
 }
 
@@ -116,21 +538,109 @@ impl Module for NativeModule {
         get_str!((self.version_fn)(self.instance), name)
     }
 
+    fn methods(&self) -> Vec<MethodDescriptor> {
+        let mut methods = Vec::<MethodDescriptor>::new();
+        (self.methods_fn)(
+            self.instance,
+            &mut methods as *mut _ as *mut (),
+            push_method,
+        );
+        methods
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        let mut dependencies = Vec::<Dependency>::new();
+        (self.dependencies_fn)(
+            self.instance,
+            &mut dependencies as *mut _ as *mut (),
+            push_dependency,
+        );
+        dependencies
+    }
+
+    fn init(&self) {
+        self.lifecycle("init", self.init_fn)
+    }
+
+    fn start(&self) {
+        self.lifecycle("start", self.start_fn)
+    }
+
     fn run(&self) {
-        if let Some(run) = self.run_fn {
-            run(self.instance);
-        }
+        self.lifecycle("run", self.run_fn)
+    }
+
+    fn stop(&self) {
+        self.lifecycle("stop", self.stop_fn)
     }
 
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
+    fn shutdown(&self) {
+        self.lifecycle("shutdown", self.shutdown_fn)
+    }
+
+    fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let mut snapshot = Vec::<u8>::new();
+        (self.snapshot_fn)(
+            self.instance,
+            &mut snapshot as *mut _ as *mut (),
+            push_bytes,
+        )
+        .then_some(snapshot)
+    }
+
+    fn restore(&self, snapshot: &[u8]) -> Result<(), String> {
+        self.call_with_reason(self.restore_fn, snapshot)
+    }
+
+    fn configure(&self, config: &[u8]) -> Result<(), String> {
+        self.call_with_reason(self.configure_fn, config)
+    }
+
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
         let method = method.into();
         let data = data.map(NativeByteSlice::from);
+        let headers = encode_headers(&context.headers);
 
         (self.invoke_fn)(
             self.instance,
             method,
             data.unwrap_or_default(),
             NativeCallback::new(callback),
+            NativeCancellationToken::new(token),
+            NativeInvocationContext::new(&context, &headers),
+        );
+    }
+
+    fn invoke_stream(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        let method = method.into();
+        let data = data.map(NativeByteSlice::from);
+        let headers = encode_headers(&context.headers);
+
+        (self.invoke_stream_fn)(
+            self.instance,
+            method,
+            data.unwrap_or_default(),
+            NativeStreamCallback::new(callback),
+            NativeCancellationToken::new(token),
+            NativeInvocationContext::new(&context, &headers),
         );
     }
 }
@@ -32,3 +32,12 @@ impl From<NativeByteSlice> for Option<&[u8]> {
         }
     }
 }
+
+/// Receives bytes from the other side of the FFI boundary; `out` is the `Vec<u8>` they are
+/// appended to.
+pub type NativePushBytes = extern "C" fn(out: *mut (), bytes: NativeByteSlice);
+
+pub(crate) extern "C" fn push_bytes(out: *mut (), bytes: NativeByteSlice) {
+    let out = unsafe { &mut *(out as *mut Vec<u8>) };
+    out.extend_from_slice(Option::<&[u8]>::from(bytes).unwrap_or_default());
+}
@@ -0,0 +1,113 @@
+use crate::errors::Error;
+use crate::*;
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Runs `f`, catching a panic before it can unwind across an `extern "C"` boundary.
+/// Returns the panic message on failure.
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(|e| panic_message(&*e))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(v) = payload.downcast_ref::<&str>() {
+        v.to_string()
+    } else if let Some(v) = payload.downcast_ref::<String>() {
+        v.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// A callback shared between the callee and the trampoline, so the trampoline can
+/// still complete it if the callee panics after taking ownership, unless the callee
+/// completed it already.
+pub(crate) struct Shared<C> {
+    callback: Arc<C>,
+    completed: Arc<AtomicBool>,
+}
+
+impl<C> Shared<C> {
+    fn complete(&self) -> &C {
+        self.completed.store(true, Ordering::SeqCst);
+        &self.callback
+    }
+}
+
+impl Callback for Shared<NativeCallback> {
+    fn on_success(&self, result: CallbackSuccess) {
+        self.complete().on_success(result)
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.complete().on_error(err)
+    }
+}
+
+impl StreamCallback for Shared<NativeStreamCallback> {
+    fn on_next(&self, result: CallbackSuccess) {
+        self.callback.on_next(result)
+    }
+
+    fn on_complete(&self) {
+        self.complete().on_complete()
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.complete().on_error(err)
+    }
+}
+
+pub(crate) trait FailOnPanic {
+    fn fail(&self, err: CallbackError);
+}
+
+impl FailOnPanic for NativeCallback {
+    fn fail(&self, err: CallbackError) {
+        Callback::on_error(self, err)
+    }
+}
+
+impl FailOnPanic for NativeStreamCallback {
+    fn fail(&self, err: CallbackError) {
+        StreamCallback::on_error(self, err)
+    }
+}
+
+/// Calls `f` with `callback`, completing the callback with `Error::Panicked`
+/// if `f` panics, and hasn't completed it yet, instead of letting the panic escape.
+pub(crate) fn invoke_catching_panic<C: FailOnPanic, R>(
+    context: &str,
+    callback: C,
+    f: impl FnOnce(Box<Shared<C>>) -> R,
+) -> Option<R> {
+    let callback = Arc::new(callback);
+    let completed = Arc::new(AtomicBool::new(false));
+    let shared = Box::new(Shared {
+        callback: callback.clone(),
+        completed: completed.clone(),
+    });
+
+    match catch_panic(|| f(shared)) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            tracing::error!("panic in {}: {}", context, e);
+
+            // the callee may have completed the callback before panicking
+            if completed.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            callback.fail(CallbackError {
+                code: Error::Panicked as i32,
+                err_name: Error::Panicked.as_ref().into(),
+                description: Some(&e),
+                data: None,
+            });
+
+            None
+        }
+    }
+}
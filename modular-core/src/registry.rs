@@ -1,27 +1,144 @@
 use crate::errors::Error;
+use crate::panic::invoke_catching_panic;
 use crate::*;
 use tracing::error;
 
 pub trait Registry: Clone + Send + Sync {
     fn run(&self) -> Result<(), Error>;
-    fn register_module(&self, module: Box<dyn Module>);
-    fn deregister_module(&self, package: &str);
-    fn invoke(&self, package: &str, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>);
+    fn register_module(&self, module: Box<dyn Module>) -> Result<(), Error>;
+    /// Fails with `Error::ModuleNotFound` if no module is registered under `package`.
+    fn deregister_module(&self, package: &str) -> Result<(), Error>;
+    /// Methods described by the module registered under `package`, `None` if there is no such module.
+    fn methods(&self, package: &str) -> Option<Vec<MethodDescriptor>>;
+
+    /// Registered modules matching `query`, ordered by package and then version.
+    fn find_modules(&self, query: &ModuleQuery) -> Vec<RegisteredModule>;
+
+    fn modules(&self) -> Vec<RegisteredModule> {
+        self.find_modules(&ModuleQuery::All)
+    }
+
+    /// The highest registered version of `package`, the one `invoke` picks.
+    fn module(&self, package: &str) -> Option<RegisteredModule> {
+        self.find_modules(&ModuleQuery::Exact(package.to_string()))
+            .pop()
+    }
+
+    /// Subscribes `watcher` to modules being registered, deregistered, started and failing,
+    /// returning the modules registered at that point along with the id of the watch.
+    /// Ids start at 1; a watch with id 0 could not be set up and delivers no events.
+    fn watch(&self, watcher: Box<dyn RegistryWatcher>) -> Watch;
+    /// Ends the watch `id`, dropping its watcher. Unknown ids are ignored.
+    fn unwatch(&self, id: u64);
+
+    /// `package` may be an address carrying a semver requirement, as in `dll.module2@^1.2`,
+    /// for registries holding several versions of a package; without one they pick the
+    /// highest version.
+    fn invoke(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+    ) -> CancellationToken {
+        self.invoke_with(package, method, data, callback, InvokeOptions::default())
+    }
+
+    fn invoke_stream(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+    ) -> CancellationToken {
+        self.invoke_stream_with(package, method, data, callback, InvokeOptions::default())
+    }
+
+    fn invoke_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        options: InvokeOptions,
+    ) -> CancellationToken;
+
+    /// `invoke` with the request encoded, and the response decoded, by `C`.
+    fn invoke_typed<C, Req, Resp, F>(
+        &self,
+        package: &str,
+        method: &str,
+        request: &Req,
+        f: F,
+    ) -> CancellationToken
+    where
+        C: Codec<Req> + Codec<Resp> + 'static,
+        Resp: 'static,
+        F: FnOnce(Result<Resp, OwnedError>) + Send + 'static,
+    {
+        let callback = TypedCallback::<C, Resp, F>::new(f);
+
+        match <C as Codec<Req>>::encode(request) {
+            Ok(data) => self.invoke(package, method, Some(&data), Box::new(callback)),
+            Err(e) => {
+                callback.on_error(OwnedError::from(e).as_error());
+                CancellationToken::new()
+            }
+        }
+    }
+
+    fn invoke_stream_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        options: InvokeOptions,
+    ) -> CancellationToken;
 }
 
 #[repr(C)]
 pub struct NativeRegistry {
+    abi: NativeAbiHeader,
     instance: *mut (),
     run: extern "C" fn(instance: *mut ()) -> Error,
-    register_module: extern "C" fn(instance: *mut (), module: NativeModule),
-    deregister_module: extern "C" fn(instance: *mut (), package: NativeByteSlice),
+    register_module: extern "C" fn(instance: *mut (), module: NativeModule) -> Error,
+    deregister_module: extern "C" fn(instance: *mut (), package: NativeByteSlice) -> Error,
+    methods: extern "C" fn(
+        instance: *mut (),
+        package: NativeByteSlice,
+        out: *mut (),
+        push: NativePushMethod,
+    ) -> bool,
+    find_modules: extern "C" fn(
+        instance: *mut (),
+        query: NativeModuleQuery,
+        out: *mut (),
+        push: NativePushModule,
+    ),
+    watch: extern "C" fn(
+        instance: *mut (),
+        watcher: NativeRegistryWatcher,
+        out: *mut (),
+        push: NativePushModule,
+    ) -> u64,
+    unwatch: extern "C" fn(instance: *mut (), id: u64),
     invoke: extern "C" fn(
         instance: *mut (),
         package: NativeByteSlice,
         method: NativeByteSlice,
         data: NativeByteSlice,
         callback: NativeCallback,
-    ),
+        options: NativeInvokeOptions,
+    ) -> NativeCancellationToken,
+    invoke_stream: extern "C" fn(
+        instance: *mut (),
+        package: NativeByteSlice,
+        method: NativeByteSlice,
+        data: NativeByteSlice,
+        callback: NativeStreamCallback,
+        options: NativeInvokeOptions,
+    ) -> NativeCancellationToken,
     clone_fn: extern "C" fn(instance: *mut ()) -> Self,
     drop: extern "C" fn(instance: *mut ()),
 }
@@ -34,11 +151,17 @@ impl NativeRegistry {
         let registry = Box::into_raw(Box::new(registry)) as *mut ();
 
         Self {
+            abi: NativeAbiHeader::new::<Self>(),
             instance: registry,
             run: Self::run::<R>,
             register_module: Self::register_module::<R>,
             deregister_module: Self::deregister_module::<R>,
+            methods: Self::methods::<R>,
+            find_modules: Self::find_modules::<R>,
+            watch: Self::watch::<R>,
+            unwatch: Self::unwatch::<R>,
             invoke: Self::invoke::<R>,
+            invoke_stream: Self::invoke_stream::<R>,
             clone_fn: Self::clone::<R>,
             drop: Self::drop::<R>,
         }
@@ -47,23 +170,126 @@ impl NativeRegistry {
     extern "C" fn run<R: Registry>(instance: *mut ()) -> Error {
         let registry = unsafe { &*(instance as *const R) };
 
-        match registry.run() {
-            Ok(()) => Error::default(),
-            Err(e) => e,
+        native_result("run", catch_panic(|| registry.run()))
+    }
+
+    extern "C" fn register_module<R: Registry>(instance: *mut (), module: NativeModule) -> Error {
+        let registry = unsafe { &*(instance as *const R) };
+
+        if let Err(e) = module.check_abi() {
+            error!("rejecting module: {}", e);
+            // the vtable can't be trusted, so the module is leaked instead of dropped
+            std::mem::forget(module);
+            return Error::InvalidModule;
         }
+
+        native_result(
+            "register_module",
+            catch_panic(|| registry.register_module(Box::new(module))),
+        )
     }
 
-    extern "C" fn register_module<R: Registry>(instance: *mut (), module: NativeModule) {
+    extern "C" fn deregister_module<R: Registry>(
+        instance: *mut (),
+        package: NativeByteSlice,
+    ) -> Error {
         let registry = unsafe { &*(instance as *const R) };
-        registry.register_module(Box::new(module));
+        let package: Option<&[u8]> = package.into();
+
+        let result = catch_panic(|| {
+            let package = get_str!(package, package);
+            registry.deregister_module(package)
+        });
+
+        native_result("deregister_module", result)
     }
 
-    extern "C" fn deregister_module<R: Registry>(instance: *mut (), package: NativeByteSlice) {
+    extern "C" fn methods<R: Registry>(
+        instance: *mut (),
+        package: NativeByteSlice,
+        out: *mut (),
+        push: NativePushMethod,
+    ) -> bool {
         let registry = unsafe { &*(instance as *const R) };
         let package: Option<&[u8]> = package.into();
 
-        let package = get_str!(package, package);
-        registry.deregister_module(package);
+        let result = catch_panic(|| {
+            let package = get_str!(package, package);
+            let methods = registry.methods(package);
+
+            for method in methods.iter().flatten() {
+                push(out, method.into());
+            }
+
+            methods.is_some()
+        });
+
+        result.unwrap_or_else(|e| {
+            error!("panic in registry methods: {}", e);
+            false
+        })
+    }
+
+    extern "C" fn find_modules<R: Registry>(
+        instance: *mut (),
+        query: NativeModuleQuery,
+        out: *mut (),
+        push: NativePushModule,
+    ) {
+        let registry = unsafe { &*(instance as *const R) };
+
+        let query = match ModuleQuery::try_from(query) {
+            Ok(v) => v,
+            Err(kind) => return error!("unknown module query kind {}", kind),
+        };
+
+        let result = catch_panic(|| {
+            for module in &registry.find_modules(&query) {
+                push(out, module.into());
+            }
+        });
+
+        if let Err(e) = result {
+            error!("panic in registry find_modules: {}", e);
+        }
+    }
+
+    extern "C" fn watch<R: Registry>(
+        instance: *mut (),
+        watcher: NativeRegistryWatcher,
+        out: *mut (),
+        push: NativePushModule,
+    ) -> u64 {
+        let registry = unsafe { &*(instance as *const R) };
+
+        if let Err(e) = watcher.check_abi() {
+            error!("rejecting registry watcher: {}", e);
+            std::mem::forget(watcher);
+            return 0;
+        }
+
+        let result = catch_panic(|| {
+            let watch = registry.watch(Box::new(watcher));
+
+            for module in &watch.modules {
+                push(out, module.into());
+            }
+
+            watch.id
+        });
+
+        result.unwrap_or_else(|e| {
+            error!("panic in registry watch: {}", e);
+            0
+        })
+    }
+
+    extern "C" fn unwatch<R: Registry>(instance: *mut (), id: u64) {
+        let registry = unsafe { &*(instance as *const R) };
+
+        if let Err(e) = catch_panic(|| registry.unwatch(id)) {
+            error!("panic in registry unwatch: {}", e);
+        }
     }
 
     extern "C" fn invoke<R: Registry>(
@@ -72,58 +298,191 @@ impl NativeRegistry {
         method: NativeByteSlice,
         data: NativeByteSlice,
         callback: NativeCallback,
-    ) {
+        options: NativeInvokeOptions,
+    ) -> NativeCancellationToken {
+        let registry = unsafe { &*(instance as *const R) };
+
+        if let Err(e) = callback.check_abi() {
+            error!("rejecting registry invoke: {}", e);
+            std::mem::forget(callback);
+            return NativeCancellationToken::new(CancellationToken::default());
+        }
+
+        let token = invoke_catching_panic("registry invoke", callback, |callback| {
+            let package = get_str!(package, package);
+            let method = get_str!(method, method);
+            let data: Option<&[u8]> = data.into();
+
+            registry.invoke_with(package, method, data, callback, options.into())
+        });
+
+        NativeCancellationToken::new(token.unwrap_or_default())
+    }
+
+    extern "C" fn invoke_stream<R: Registry>(
+        instance: *mut (),
+        package: NativeByteSlice,
+        method: NativeByteSlice,
+        data: NativeByteSlice,
+        callback: NativeStreamCallback,
+        options: NativeInvokeOptions,
+    ) -> NativeCancellationToken {
         let registry = unsafe { &*(instance as *const R) };
-        let package = get_str!(package, package);
-        let method = get_str!(method, method);
-        let data: Option<&[u8]> = data.into();
 
-        registry.invoke(package, method, data, Box::new(callback));
+        if let Err(e) = callback.check_abi() {
+            error!("rejecting registry invoke_stream: {}", e);
+            std::mem::forget(callback);
+            return NativeCancellationToken::new(CancellationToken::default());
+        }
+
+        let token = invoke_catching_panic("registry invoke_stream", callback, |callback| {
+            let package = get_str!(package, package);
+            let method = get_str!(method, method);
+            let data: Option<&[u8]> = data.into();
+
+            registry.invoke_stream_with(package, method, data, callback, options.into())
+        });
+
+        NativeCancellationToken::new(token.unwrap_or_default())
     }
 
     extern "C" fn drop<R: Registry + 'static>(instance: *mut ()) {
-        let _ = unsafe { Box::from_raw(instance as *mut R) };
+        let registry = unsafe { Box::from_raw(instance as *mut R) };
+
+        if let Err(e) = catch_panic(|| drop(registry)) {
+            error!("panic in registry drop: {}", e);
+        }
     }
 
     extern "C" fn clone<R: Registry + 'static>(instance: *mut ()) -> Self {
-        let instance = unsafe { &*(instance as *const R) }.clone();
-        Self::new(instance)
+        let registry = unsafe { &*(instance as *const R) };
+
+        match catch_panic(|| registry.clone()) {
+            Ok(registry) => Self::new(registry),
+            Err(e) => {
+                // there is no registry to hand back, and unwinding further is undefined behavior
+                error!("panic in registry clone: {}", e);
+                std::process::abort()
+            }
+        }
     }
 }
 
-impl Registry for NativeRegistry {
-    fn run(&self) -> Result<(), Error> {
-        let e = (self.run)(self.instance);
+impl NativeAbi for NativeRegistry {
+    const NAME: &'static str = "NativeRegistry";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
+    }
+}
 
-        if e == Error::NoError {
-            Ok(())
-        } else {
-            Err(e)
+/// Flattens the result of a registry operation run by a trampoline into the `Error` it returns.
+fn native_result(operation: &str, result: Result<Result<(), Error>, String>) -> Error {
+    match result {
+        Ok(Ok(())) => Error::NoError,
+        Ok(Err(e)) => e,
+        Err(e) => {
+            error!("panic in registry {}: {}", operation, e);
+            Error::Panicked
         }
     }
+}
+
+impl Registry for NativeRegistry {
+    fn run(&self) -> Result<(), Error> {
+        (self.run)(self.instance).into_result()
+    }
 
-    fn register_module(&self, module: Box<dyn Module>) {
+    fn register_module(&self, module: Box<dyn Module>) -> Result<(), Error> {
         let module = NativeModule::new(module);
-        (self.register_module)(self.instance, module)
+        (self.register_module)(self.instance, module).into_result()
     }
 
-    fn deregister_module(&self, package: &str) {
+    fn deregister_module(&self, package: &str) -> Result<(), Error> {
         let package = NativeByteSlice::from(package);
-        (self.deregister_module)(self.instance, package)
+        (self.deregister_module)(self.instance, package).into_result()
     }
 
-    fn invoke(
+    fn methods(&self, package: &str) -> Option<Vec<MethodDescriptor>> {
+        let package = NativeByteSlice::from(package);
+        let mut methods = Vec::<MethodDescriptor>::new();
+
+        let found = (self.methods)(
+            self.instance,
+            package,
+            &mut methods as *mut _ as *mut (),
+            push_method,
+        );
+
+        found.then_some(methods)
+    }
+
+    fn find_modules(&self, query: &ModuleQuery) -> Vec<RegisteredModule> {
+        let mut modules = Vec::<RegisteredModule>::new();
+
+        (self.find_modules)(
+            self.instance,
+            query.into(),
+            &mut modules as *mut _ as *mut (),
+            push_module,
+        );
+
+        modules
+    }
+
+    fn watch(&self, watcher: Box<dyn RegistryWatcher>) -> Watch {
+        let mut modules = Vec::<RegisteredModule>::new();
+
+        let id = (self.watch)(
+            self.instance,
+            NativeRegistryWatcher::new(watcher),
+            &mut modules as *mut _ as *mut (),
+            push_module,
+        );
+
+        Watch { id, modules }
+    }
+
+    fn unwatch(&self, id: u64) {
+        (self.unwatch)(self.instance, id)
+    }
+
+    fn invoke_with(
         &self,
         package: &str,
         method: &str,
         data: Option<&[u8]>,
         callback: Box<dyn Callback>,
-    ) {
+        options: InvokeOptions,
+    ) -> CancellationToken {
         let package = NativeByteSlice::from(package);
         let method = NativeByteSlice::from(method);
         let data = data.map(NativeByteSlice::from).unwrap_or_default();
         let callback = NativeCallback::new(callback);
-        (self.invoke)(self.instance, package, method, data, callback)
+        // this side of the boundary knows the invocation the call is made from
+        let parent = options.parent.or_else(current_invocation);
+        let headers = encode_headers(&options.headers);
+        let options = NativeInvokeOptions::new(&InvokeOptions { parent, ..options }, &headers);
+        (self.invoke)(self.instance, package, method, data, callback, options).into()
+    }
+
+    fn invoke_stream_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        let package = NativeByteSlice::from(package);
+        let method = NativeByteSlice::from(method);
+        let data = data.map(NativeByteSlice::from).unwrap_or_default();
+        let callback = NativeStreamCallback::new(callback);
+        // this side of the boundary knows the invocation the call is made from
+        let parent = options.parent.or_else(current_invocation);
+        let headers = encode_headers(&options.headers);
+        let options = NativeInvokeOptions::new(&InvokeOptions { parent, ..options }, &headers);
+        (self.invoke_stream)(self.instance, package, method, data, callback, options).into()
     }
 }
 
@@ -0,0 +1,167 @@
+use crate::*;
+use std::sync::Arc;
+use tracing::error;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RegistryEventKind {
+    ModuleRegistered = 0,
+    ModuleDeregistered = 1,
+    ModuleStarted = 2,
+    ModuleFailed = 3,
+}
+
+impl TryFrom<i32> for RegistryEventKind {
+    type Error = i32;
+
+    fn try_from(v: i32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::ModuleRegistered),
+            1 => Ok(Self::ModuleDeregistered),
+            2 => Ok(Self::ModuleStarted),
+            3 => Ok(Self::ModuleFailed),
+            v => Err(v),
+        }
+    }
+}
+
+/// A change to the modules of a registry, delivered to its watchers.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RegistryEvent {
+    pub kind: RegistryEventKind,
+    pub package: String,
+    pub version: String,
+}
+
+/// Outcome of `Registry::watch`: the id to `unwatch` with, and the modules registered
+/// when the watch began.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Watch {
+    pub id: u64,
+    pub modules: Vec<RegisteredModule>,
+}
+
+/// Receives the events of a registry, on the thread making each change. Changes made
+/// while `watch` is running may show up both in its snapshot and as an event.
+pub trait RegistryWatcher: Send + Sync {
+    fn on_event(&self, event: &RegistryEvent);
+}
+
+impl<F: Fn(&RegistryEvent) + Send + Sync> RegistryWatcher for F {
+    fn on_event(&self, event: &RegistryEvent) {
+        self(event)
+    }
+}
+
+impl RegistryWatcher for Box<dyn RegistryWatcher> {
+    fn on_event(&self, event: &RegistryEvent) {
+        (**self).on_event(event)
+    }
+}
+
+impl<W: RegistryWatcher + ?Sized> RegistryWatcher for Arc<W> {
+    fn on_event(&self, event: &RegistryEvent) {
+        (**self).on_event(event)
+    }
+}
+
+/// Borrows from the `RegistryEvent` it was created from, like `NativeMethodDescriptor`.
+#[repr(C)]
+pub struct NativeRegistryEvent {
+    kind: i32,
+    package: NativeByteSlice,
+    version: NativeByteSlice,
+}
+
+impl From<&RegistryEvent> for NativeRegistryEvent {
+    fn from(event: &RegistryEvent) -> Self {
+        Self {
+            kind: event.kind as i32,
+            package: NativeByteSlice::from(&event.package),
+            version: NativeByteSlice::from(&event.version),
+        }
+    }
+}
+
+impl TryFrom<NativeRegistryEvent> for RegistryEvent {
+    type Error = i32;
+
+    fn try_from(event: NativeRegistryEvent) -> Result<Self, Self::Error> {
+        Ok(Self {
+            kind: event.kind.try_into()?,
+            package: native_string(event.package),
+            version: native_string(event.version),
+        })
+    }
+}
+
+pub(crate) fn native_string(s: NativeByteSlice) -> String {
+    Option::<&[u8]>::from(s)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .unwrap_or_default()
+}
+
+#[repr(C)]
+pub struct NativeRegistryWatcher {
+    abi: NativeAbiHeader,
+    instance: *mut (),
+    on_event: extern "C" fn(*mut (), NativeRegistryEvent),
+    drop: extern "C" fn(*mut ()),
+}
+
+unsafe impl Send for NativeRegistryWatcher {}
+unsafe impl Sync for NativeRegistryWatcher {}
+
+impl NativeRegistryWatcher {
+    pub fn new<T: RegistryWatcher + 'static>(watcher: T) -> Self {
+        let instance = Box::into_raw(Box::new(watcher)) as *mut ();
+
+        Self {
+            abi: NativeAbiHeader::new::<Self>(),
+            instance,
+            on_event: Self::on_event::<T>,
+            drop: Self::drop::<T>,
+        }
+    }
+
+    extern "C" fn on_event<T: RegistryWatcher>(instance: *mut (), event: NativeRegistryEvent) {
+        let watcher = unsafe { &*(instance as *const T) };
+
+        let event = match RegistryEvent::try_from(event) {
+            Ok(v) => v,
+            Err(kind) => return error!("unknown registry event kind {}", kind),
+        };
+
+        if let Err(e) = catch_panic(|| watcher.on_event(&event)) {
+            error!("panic in registry watcher: {}", e);
+        }
+    }
+
+    extern "C" fn drop<T: RegistryWatcher>(instance: *mut ()) {
+        let watcher = unsafe { Box::from_raw(instance as *mut T) };
+
+        if let Err(e) = catch_panic(|| drop(watcher)) {
+            error!("panic in registry watcher drop: {}", e);
+        }
+    }
+}
+
+impl NativeAbi for NativeRegistryWatcher {
+    const NAME: &'static str = "NativeRegistryWatcher";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
+    }
+}
+
+impl RegistryWatcher for NativeRegistryWatcher {
+    fn on_event(&self, event: &RegistryEvent) {
+        (self.on_event)(self.instance, event.into())
+    }
+}
+
+impl Drop for NativeRegistryWatcher {
+    fn drop(&mut self) {
+        (self.drop)(self.instance)
+    }
+}
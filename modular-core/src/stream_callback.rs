@@ -0,0 +1,141 @@
+use crate::*;
+use std::sync::Arc;
+use tracing::error;
+
+/// Receives any number of `on_next` messages, terminated by exactly one
+/// `on_complete` or `on_error`.
+pub trait StreamCallback: Send + Sync {
+    fn on_next(&self, result: CallbackSuccess);
+    fn on_complete(&self);
+    fn on_error(&self, err: CallbackError);
+}
+
+impl StreamCallback for Box<dyn StreamCallback> {
+    fn on_next(&self, result: CallbackSuccess) {
+        (**self).on_next(result);
+    }
+
+    fn on_complete(&self) {
+        (**self).on_complete();
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        (**self).on_error(err);
+    }
+}
+
+impl<C: StreamCallback + ?Sized> StreamCallback for Arc<C> {
+    fn on_next(&self, result: CallbackSuccess) {
+        (**self).on_next(result);
+    }
+
+    fn on_complete(&self) {
+        (**self).on_complete();
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        (**self).on_error(err);
+    }
+}
+
+/// Adapts a single-response `Callback` invocation to a stream of exactly one message.
+pub struct SingleMessageStream(pub Box<dyn StreamCallback>);
+
+impl Callback for SingleMessageStream {
+    fn on_success(&self, result: CallbackSuccess) {
+        self.0.on_next(result);
+        self.0.on_complete();
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.0.on_error(err);
+    }
+}
+
+#[repr(C)]
+pub struct NativeStreamCallback {
+    abi: NativeAbiHeader,
+    instance: *mut (),
+    on_next: extern "C" fn(*mut (), NativeCallbackSuccess),
+    on_complete: extern "C" fn(*mut ()),
+    on_error: extern "C" fn(*mut (), NativeCallbackError),
+    drop: extern "C" fn(*mut ()),
+}
+
+unsafe impl Send for NativeStreamCallback {}
+unsafe impl Sync for NativeStreamCallback {}
+
+impl NativeStreamCallback {
+    pub fn new<T: StreamCallback + 'static>(callback: T) -> Self {
+        let instance = Box::into_raw(Box::new(callback)) as *mut ();
+
+        Self {
+            abi: NativeAbiHeader::new::<Self>(),
+            instance,
+            on_next: Self::on_next::<T>,
+            on_complete: Self::on_complete::<T>,
+            on_error: Self::on_error::<T>,
+            drop: Self::drop::<T>,
+        }
+    }
+
+    extern "C" fn on_next<T: StreamCallback>(instance: *mut (), result: NativeCallbackSuccess) {
+        let callback = unsafe { &*(instance as *const T) };
+
+        if let Err(e) = catch_panic(|| callback.on_next(result.into())) {
+            error!("panic in stream callback on_next: {}", e);
+        }
+    }
+
+    extern "C" fn on_complete<T: StreamCallback>(instance: *mut ()) {
+        let callback = unsafe { &*(instance as *const T) };
+
+        if let Err(e) = catch_panic(|| callback.on_complete()) {
+            error!("panic in stream callback on_complete: {}", e);
+        }
+    }
+
+    extern "C" fn on_error<T: StreamCallback>(instance: *mut (), err: NativeCallbackError) {
+        let callback = unsafe { &*(instance as *const T) };
+
+        if let Err(e) = catch_panic(|| callback.on_error(err.into())) {
+            error!("panic in stream callback on_error: {}", e);
+        }
+    }
+
+    extern "C" fn drop<T: StreamCallback>(instance: *mut ()) {
+        let callback = unsafe { Box::from_raw(instance as *mut T) };
+
+        if let Err(e) = catch_panic(|| drop(callback)) {
+            error!("panic in stream callback drop: {}", e);
+        }
+    }
+}
+
+impl NativeAbi for NativeStreamCallback {
+    const NAME: &'static str = "NativeStreamCallback";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
+    }
+}
+
+impl StreamCallback for NativeStreamCallback {
+    fn on_next(&self, result: CallbackSuccess) {
+        (self.on_next)(self.instance, result.into());
+    }
+
+    fn on_complete(&self) {
+        (self.on_complete)(self.instance);
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        (self.on_error)(self.instance, err.into());
+    }
+}
+
+impl Drop for NativeStreamCallback {
+    fn drop(&mut self) {
+        (self.drop)(self.instance);
+    }
+}
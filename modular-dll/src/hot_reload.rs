@@ -0,0 +1,127 @@
+use crate::DllModule;
+use modular_core::{Error, Module, Registry};
+use native_recorder::BytesRecorder;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+/// How often `HotReload` looks at the library file.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the library of a `DllModule` and reloads it whenever it is rebuilt, handing
+/// each new build to `swap`, as in `move |m| modular.swap_module(Box::new(m))`. A build
+/// that fails to load, or to swap in, is logged and the loaded module kept. Watching
+/// stops when dropped.
+pub struct HotReload {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HotReload {
+    pub fn new<P, R, L, F>(path: P, registry: R, recorder: L, swap: F) -> Self
+    where
+        P: Into<PathBuf>,
+        R: Registry + 'static,
+        L: BytesRecorder + 'static,
+        F: Fn(DllModule) -> Result<(), Error> + Send + 'static,
+    {
+        let path = path.into();
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut loaded = stamp(&path);
+            let mut seen = loaded;
+
+            // stops once the sender is dropped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                let current = stamp(&path);
+
+                // a build still being written changes between polls, so wait for the
+                // file to stay the same for one interval
+                if current.is_none() || current == loaded || current != seen {
+                    seen = current;
+                    continue;
+                }
+                loaded = current;
+
+                let module = match DllModule::new_copy(&path, &registry, recorder.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(
+                            "failed to reload {}, keeping the loaded module: {}",
+                            path.display(),
+                            e
+                        );
+                        continue;
+                    }
+                };
+
+                let package = module.package().to_string();
+                match swap(module) {
+                    Ok(()) => info!("reloaded module {:?} from {}", package, path.display()),
+                    Err(e) => error!("failed to swap in module {:?}: {}", package, e.as_ref()),
+                }
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for HotReload {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// What tells builds of a library apart.
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// A copy of a library in the temporary directory, removed when dropped.
+pub(crate) struct LibraryCopy(PathBuf);
+
+impl LibraryCopy {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut name = format!(
+            "{}-{}-{}",
+            stem,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        if let Some(extension) = path.extension() {
+            name = format!("{}.{}", name, extension.to_string_lossy());
+        }
+
+        let copy = std::env::temp_dir().join(name);
+        fs::copy(path, &copy)?;
+
+        Ok(Self(copy))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for LibraryCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
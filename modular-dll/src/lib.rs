@@ -1,12 +1,60 @@
+mod hot_reload;
+
 use modular_core::{Callback, Module, NativeModule, NativeRegistry, Registry};
 use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+pub use hot_reload::*;
 
 pub use modular_core::*;
 use native_recorder::{BytesRecorder, NativeBytesRecorder};
 
+#[derive(Debug)]
+pub enum DllModuleError {
+    Library(libloading::Error),
+    /// Also for a library not exporting `ABI_SYMBOL`, found as version 0.
+    AbiMismatch(AbiMismatch),
+    Copy(std::io::Error),
+}
+
+impl Display for DllModuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Library(e) => write!(f, "failed to load library: {}", e),
+            Self::AbiMismatch(e) => write!(f, "incompatible module: {}", e),
+            Self::Copy(e) => write!(f, "failed to copy library: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DllModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Library(e) => Some(e),
+            Self::AbiMismatch(e) => Some(e),
+            Self::Copy(e) => Some(e),
+        }
+    }
+}
+
+impl From<libloading::Error> for DllModuleError {
+    fn from(e: libloading::Error) -> Self {
+        Self::Library(e)
+    }
+}
+
+impl From<AbiMismatch> for DllModuleError {
+    fn from(e: AbiMismatch) -> Self {
+        Self::AbiMismatch(e)
+    }
+}
+
 pub struct DllModule {
-    _lib: libloading::Library,
     module: NativeModule,
+    _lib: libloading::Library,
+    // removed once the library is unloaded
+    _copy: Option<LibraryCopy>,
 }
 
 impl DllModule {
@@ -14,10 +62,43 @@ impl DllModule {
         path: S,
         registry: &R,
         recorder: L,
-    ) -> Result<Self, libloading::Error> {
+    ) -> Result<Self, DllModuleError> {
+        Self::load(path, registry, recorder, None)
+    }
+
+    /// Loads a temporary copy of the library at `path` rather than the library itself, so
+    /// that it can be rebuilt in place while loaded, and so that a new build gets loaded
+    /// even if the loader has the previous one open already.
+    pub fn new_copy<P: AsRef<Path>, R: Registry + 'static, L: BytesRecorder + 'static>(
+        path: P,
+        registry: &R,
+        recorder: L,
+    ) -> Result<Self, DllModuleError> {
+        let copy = LibraryCopy::new(path.as_ref()).map_err(DllModuleError::Copy)?;
+        let path = copy.path().to_path_buf();
+        Self::load(path, registry, recorder, Some(copy))
+    }
+
+    fn load<S: AsRef<OsStr>, R: Registry + 'static, L: BytesRecorder + 'static>(
+        path: S,
+        registry: &R,
+        recorder: L,
+        copy: Option<LibraryCopy>,
+    ) -> Result<Self, DllModuleError> {
         unsafe {
             let lib = libloading::Library::new(path)?;
 
+            // checked before anything is passed to the module, a library without the
+            // symbol being too old to have it
+            let abi = match lib.get::<extern "C" fn() -> NativeAbiHeader>(ABI_SYMBOL) {
+                Ok(abi) => abi(),
+                Err(_) => NativeAbiHeader {
+                    version: 0,
+                    size: 0,
+                },
+            };
+            abi.check::<NativeModule>("NativeModule")?;
+
             let create_module = lib
                 .get::<unsafe extern "C" fn(NativeRegistry, NativeBytesRecorder) -> NativeModule>(
                     b"create_module",
@@ -28,7 +109,19 @@ impl DllModule {
                 NativeBytesRecorder::new(recorder),
             );
 
-            Ok(Self { _lib: lib, module })
+            if let Err(e) = module.check_abi() {
+                // the vtable can't be trusted, so the module is leaked instead of dropped,
+                // and its code kept loaded for what it may have started with the registry
+                std::mem::forget(module);
+                std::mem::forget(lib);
+                return Err(e.into());
+            }
+
+            Ok(Self {
+                module,
+                _lib: lib,
+                _copy: copy,
+            })
         }
     }
 }
@@ -42,11 +135,74 @@ impl Module for DllModule {
         self.module.version()
     }
 
+    fn kind(&self) -> ModuleKind {
+        ModuleKind::Dll
+    }
+
+    fn methods(&self) -> Vec<MethodDescriptor> {
+        self.module.methods()
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        self.module.dependencies()
+    }
+
+    fn init(&self) {
+        self.module.init()
+    }
+
+    fn start(&self) {
+        self.module.start()
+    }
+
     fn run(&self) {
         self.module.run()
     }
 
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
-        self.module.invoke(method, data, callback)
+    fn stop(&self) {
+        self.module.stop()
+    }
+
+    fn shutdown(&self) {
+        self.module.shutdown()
+    }
+
+    fn is_failed(&self) -> bool {
+        self.module.is_failed()
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        self.module.snapshot()
+    }
+
+    fn restore(&self, snapshot: &[u8]) -> Result<(), String> {
+        self.module.restore(snapshot)
+    }
+
+    fn configure(&self, config: &[u8]) -> Result<(), String> {
+        self.module.configure(config)
+    }
+
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.module.invoke(method, data, callback, token, context)
+    }
+
+    fn invoke_stream(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.module
+            .invoke_stream(method, data, callback, token, context)
     }
 }
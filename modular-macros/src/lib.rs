@@ -0,0 +1,549 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    Expr, FnArg, GenericArgument, ImplItem, ImplItemFn, ItemImpl, Lit, LitStr, MetaNameValue,
+    PathArguments, ReturnType, Token, Type,
+};
+
+/// Implements `Module` for the type of an inherent impl block.
+///
+/// ```ignore
+/// #[modular_core::module(package = "dll.echo", version = "1.0.0")]
+/// impl Echo {
+///     /// Returns the message it was given.
+///     #[method(idempotent)]
+///     fn echo(&self, message: String) -> String {
+///         message
+///     }
+/// }
+/// ```
+///
+/// Every `#[method]` fn becomes a dispatch arm of `Module::invoke` and an entry of
+/// `Module::methods`, named after the fn unless `#[method(name = "...")]` says otherwise.
+/// Its arguments are decoded from the payload, as a tuple if there is more than one, and
+/// its result is encoded as the response; a `Result` is responded to with `on_error` when
+/// it is `Err`, its error type converting into `OwnedError`. An argument of type
+/// `CancellationToken` receives the token of the invocation instead of payload data, one
+/// of type `InvocationContext` its context.
+///
+/// Dependencies are declared as `dependencies = [("dll.other", "^1.2")]`.
+///
+/// Payloads are encoded with `Json` unless another `Codec` is given, as in
+/// `codec = modular_core::MsgPack`; without the `json` feature of modular-core, one has to
+/// be.
+///
+/// Non-method `fn init(&self)`, `start`, `run`, `stop` and `shutdown` in the block become
+/// the `Module` lifecycle hooks of the same name, as do
+/// `fn snapshot(&self) -> Option<Vec<u8>>`,
+/// `fn restore(&self, snapshot: &[u8]) -> Result<(), String>` and
+/// `fn configure(&self, config: &[u8]) -> Result<(), String>`.
+///
+/// Unless `export = false` is given, the module is also exported: as `__wm_create` on wasm
+/// targets, which requires `Default`, and as `create_module` and `modular_abi` otherwise,
+/// which requires `fn new(registry: NativeRegistry) -> Self` and the `native-recorder` crate.
+#[proc_macro_attribute]
+pub fn module(args: TokenStream, input: TokenStream) -> TokenStream {
+    let result = ModuleArgs::parse(args.into()).and_then(|args| {
+        let item = syn::parse::<ItemImpl>(input)?;
+        expand(args, item)
+    });
+
+    result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+struct ModuleArgs {
+    package: LitStr,
+    version: LitStr,
+    codec: Option<syn::Path>,
+    dependencies: Vec<(LitStr, LitStr)>,
+    export: bool,
+}
+
+impl ModuleArgs {
+    fn parse(args: TokenStream2) -> syn::Result<Self> {
+        let span = args.span();
+        let args = Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse2(args)?;
+
+        let mut package = None;
+        let mut version = None;
+        let mut codec = None;
+        let mut dependencies = vec![];
+        let mut export = true;
+
+        for arg in args {
+            let lit = match &arg.value {
+                Expr::Lit(lit) => Some(&lit.lit),
+                _ => None,
+            };
+
+            match (arg.path.get_ident().map(|i| i.to_string()).as_deref(), lit) {
+                (Some("package"), Some(Lit::Str(s))) => package = Some(s.clone()),
+                (Some("version"), Some(Lit::Str(s))) => version = Some(s.clone()),
+                (Some("export"), Some(Lit::Bool(b))) => export = b.value,
+                (Some("codec"), None) => match &arg.value {
+                    Expr::Path(p) => codec = Some(p.path.clone()),
+                    _ => return Err(syn::Error::new(arg.value.span(), "expected a path")),
+                },
+                (Some("dependencies"), None) => dependencies = parse_dependencies(&arg.value)?,
+                _ => {
+                    return Err(syn::Error::new(
+                        arg.span(),
+                        "expected `package = \"...\"`, `version = \"...\"`, `codec = Path`, `dependencies = [...]` or `export = bool`",
+                    ))
+                }
+            }
+        }
+
+        Ok(Self {
+            package: package.ok_or_else(|| syn::Error::new(span, "missing `package`"))?,
+            version: version.ok_or_else(|| syn::Error::new(span, "missing `version`"))?,
+            codec,
+            dependencies,
+            export,
+        })
+    }
+}
+
+/// Parses `[("package", "version requirement"), ...]`.
+fn parse_dependencies(value: &Expr) -> syn::Result<Vec<(LitStr, LitStr)>> {
+    let error = || {
+        syn::Error::new(
+            value.span(),
+            "expected `[(\"package\", \"version requirement\"), ...]`",
+        )
+    };
+    let lit_str = |e: &Expr| match e {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Str(s), ..
+        }) => Some(s.clone()),
+        _ => None,
+    };
+
+    let array = match value {
+        Expr::Array(array) => array,
+        _ => return Err(error()),
+    };
+
+    array
+        .elems
+        .iter()
+        .map(|elem| match elem {
+            Expr::Tuple(t) if t.elems.len() == 2 => lit_str(&t.elems[0])
+                .zip(lit_str(&t.elems[1]))
+                .ok_or_else(error),
+            _ => Err(error()),
+        })
+        .collect()
+}
+
+struct Method {
+    name: String,
+    ident: syn::Ident,
+    description: Option<String>,
+    idempotent: bool,
+    payload: Vec<Type>,
+    // `None` for payload arguments, which are passed in order
+    args: Vec<Option<TokenStream2>>,
+    output: Output,
+}
+
+enum Output {
+    Unit,
+    Value(Type),
+    Result(Type),
+}
+
+fn expand(args: ModuleArgs, mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new(
+            path.span(),
+            "#[module] goes on an inherent impl block",
+        ));
+    }
+
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "#[module] doesn't support generics",
+        ));
+    }
+
+    let mut methods = vec![];
+    let mut hooks = vec![];
+    let mut snapshot = false;
+    let mut restore = false;
+    let mut configure = false;
+
+    for impl_item in &mut item.items {
+        if let ImplItem::Fn(f) = impl_item {
+            match take_method_attr(f)? {
+                Some((name, idempotent)) => methods.push(parse_method(f, name, idempotent)?),
+                None if is_hook(f) => hooks.push(f.sig.ident.clone()),
+                None if f.sig.ident == "snapshot" && f.sig.inputs.len() == 1 => snapshot = true,
+                None if f.sig.ident == "restore" && f.sig.inputs.len() == 2 => restore = true,
+                None if f.sig.ident == "configure" && f.sig.inputs.len() == 2 => configure = true,
+                None => {}
+            }
+        }
+    }
+
+    let ty = &item.self_ty;
+    let ModuleArgs {
+        package, version, ..
+    } = &args;
+
+    let dependencies = args
+        .dependencies
+        .iter()
+        .map(|(package, version)| quote!(__modular::Dependency::new(#package, #version)));
+
+    let descriptors = methods.iter().map(descriptor);
+    let codec = match &args.codec {
+        Some(codec) => quote!(type __Codec = #codec;),
+        // declared by modular-core, which knows whether it has `Json`
+        None => quote!(__modular::__default_codec!(__Codec);),
+    };
+    let arms = methods.iter().map(|m| arm(m, &quote!(__Codec)));
+    let run = (!hooks.iter().any(|h| h == "run")).then(|| {
+        quote!(
+            fn run(&self) {}
+        )
+    });
+    let snapshot = snapshot.then(|| {
+        quote!(
+            fn snapshot(&self) -> ::std::option::Option<::std::vec::Vec<u8>> {
+                <#ty>::snapshot(self)
+            }
+        )
+    });
+    let restore = restore.then(|| {
+        quote!(
+            fn restore(&self, snapshot: &[u8]) -> ::std::result::Result<(), ::std::string::String> {
+                <#ty>::restore(self, snapshot)
+            }
+        )
+    });
+    let configure = configure.then(|| {
+        quote!(
+            fn configure(&self, config: &[u8]) -> ::std::result::Result<(), ::std::string::String> {
+                <#ty>::configure(self, config)
+            }
+        )
+    });
+    let export = args.export.then(|| export(ty));
+
+    Ok(quote! {
+        #item
+
+        const _: () = {
+            #[cfg(target_arch = "wasm32")]
+            use ::wasm_module_core as __modular;
+            #[cfg(not(target_arch = "wasm32"))]
+            use ::modular_core as __modular;
+
+            #codec
+
+            impl __modular::Module for #ty {
+                fn package(&self) -> &str {
+                    #package
+                }
+
+                fn version(&self) -> &str {
+                    #version
+                }
+
+                fn methods(&self) -> ::std::vec::Vec<__modular::MethodDescriptor> {
+                    ::std::vec![#(#descriptors),*]
+                }
+
+                fn dependencies(&self) -> ::std::vec::Vec<__modular::Dependency> {
+                    ::std::vec![#(#dependencies),*]
+                }
+
+                #(
+                    fn #hooks(&self) {
+                        <#ty>::#hooks(self)
+                    }
+                )*
+
+                #run
+
+                #snapshot
+
+                #restore
+
+                #configure
+
+                fn invoke(
+                    &self,
+                    method: &str,
+                    data: ::std::option::Option<&[u8]>,
+                    callback: ::std::boxed::Box<dyn __modular::Callback>,
+                    token: __modular::CancellationToken,
+                    context: __modular::InvocationContext,
+                ) {
+                    let _ = (&token, &context);
+
+                    match method {
+                        #(#arms)*
+                        _ => __modular::macro_support::method_not_found(&*callback, #package, method),
+                    }
+                }
+            }
+        };
+
+        #export
+    })
+}
+
+const HOOKS: [&str; 5] = ["init", "start", "run", "stop", "shutdown"];
+
+fn is_hook(f: &ImplItemFn) -> bool {
+    HOOKS.iter().any(|h| f.sig.ident == h) && f.sig.inputs.len() == 1
+}
+
+/// Removes `#[method]` from `f`, returning its name and whether it is idempotent.
+fn take_method_attr(f: &mut ImplItemFn) -> syn::Result<Option<(String, bool)>> {
+    let index = match f.attrs.iter().position(|a| a.path().is_ident("method")) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let attr = f.attrs.remove(index);
+
+    let mut name = f.sig.ident.to_string();
+    let mut idempotent = false;
+
+    if !matches!(attr.meta, syn::Meta::Path(_)) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("idempotent") {
+                idempotent = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"` or `idempotent`"))
+            }
+        })?;
+    }
+
+    Ok(Some((name, idempotent)))
+}
+
+fn parse_method(f: &ImplItemFn, name: String, idempotent: bool) -> syn::Result<Method> {
+    let mut inputs = f.sig.inputs.iter();
+
+    match inputs.next() {
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none() => {}
+        _ => {
+            return Err(syn::Error::new(
+                f.sig.span(),
+                "a #[method] must take `&self` first",
+            ))
+        }
+    }
+
+    let mut payload = vec![];
+    let mut args = vec![];
+
+    for input in inputs {
+        let ty = match input {
+            FnArg::Typed(t) => &*t.ty,
+            FnArg::Receiver(r) => return Err(syn::Error::new(r.span(), "unexpected receiver")),
+        };
+
+        if last_segment(ty).is_some_and(|s| s.ident == "CancellationToken") {
+            args.push(Some(quote!(token.clone())));
+        } else if last_segment(ty).is_some_and(|s| s.ident == "InvocationContext") {
+            args.push(Some(quote!(context.clone())));
+        } else {
+            payload.push(ty.clone());
+            args.push(None);
+        }
+    }
+
+    let output = match &f.sig.output {
+        ReturnType::Default => Output::Unit,
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Tuple(t) if t.elems.is_empty() => Output::Unit,
+            ty if last_segment(ty).is_some_and(|s| s.ident == "Result") => {
+                Output::Result(ty.clone())
+            }
+            ty => Output::Value(ty.clone()),
+        },
+    };
+
+    Ok(Method {
+        name,
+        ident: f.sig.ident.clone(),
+        description: doc_comment(&f.attrs),
+        idempotent,
+        payload,
+        args,
+        output,
+    })
+}
+
+fn last_segment(ty: &Type) -> Option<&syn::PathSegment> {
+    match ty {
+        Type::Path(p) => p.path.segments.last(),
+        _ => None,
+    }
+}
+
+fn doc_comment(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines = attrs
+        .iter()
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(syn::ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+fn schema_name(ty: &impl ToTokens) -> String {
+    ty.to_token_stream().to_string().replace(' ', "")
+}
+
+fn optional_str(s: Option<String>) -> TokenStream2 {
+    match s {
+        Some(s) => quote!(::std::option::Option::Some(::std::string::String::from(#s))),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn descriptor(method: &Method) -> TokenStream2 {
+    let name = &method.name;
+    let idempotent = method.idempotent;
+    let description = optional_str(method.description.clone());
+
+    let request_schema = optional_str(match method.payload.as_slice() {
+        [] => None,
+        [ty] => Some(schema_name(ty)),
+        types => Some(schema_name(&quote!((#(#types),*)))),
+    });
+
+    let response_schema = optional_str(match &method.output {
+        Output::Unit => None,
+        Output::Value(ty) => Some(schema_name(ty)),
+        Output::Result(ty) => match last_segment(ty).map(|s| &s.arguments) {
+            Some(PathArguments::AngleBracketed(a)) => match a.args.first() {
+                Some(GenericArgument::Type(ty)) => Some(schema_name(ty)),
+                _ => None,
+            },
+            _ => None,
+        },
+    });
+
+    quote! {
+        __modular::MethodDescriptor {
+            name: ::std::string::String::from(#name),
+            description: #description,
+            request_schema: #request_schema,
+            response_schema: #response_schema,
+            streaming: false,
+            idempotent: #idempotent,
+        }
+    }
+}
+
+fn arm(method: &Method, codec: &TokenStream2) -> TokenStream2 {
+    let name = &method.name;
+    let ident = &method.ident;
+
+    let vars = (0..method.payload.len())
+        .map(|i| quote::format_ident!("arg{}", i))
+        .collect::<Vec<_>>();
+    let types = &method.payload;
+
+    let decode = match vars.as_slice() {
+        [] => quote!(),
+        [var] => quote!(let #var: #(#types)* =),
+        vars => quote!(let (#(#vars),*): (#(#types),*) =),
+    };
+    let decode = (!vars.is_empty()).then(|| {
+        quote! {
+            #decode match __modular::macro_support::decode::<#codec, _>(method, data) {
+                ::std::result::Result::Ok(v) => v,
+                ::std::result::Result::Err(e) => return callback.on_error(e.as_error()),
+            };
+        }
+    });
+
+    let mut vars = vars.iter();
+    let args = method.args.iter().map(|a| {
+        a.clone()
+            .unwrap_or_else(|| vars.next().unwrap().to_token_stream())
+    });
+    let call = quote!(self.#ident(#(#args),*));
+
+    let respond = match method.output {
+        Output::Unit => quote! {
+            #call;
+            __modular::macro_support::respond_unit(&*callback)
+        },
+        Output::Value(_) => quote! {
+            __modular::macro_support::respond::<#codec, _>(&*callback, method, &#call)
+        },
+        Output::Result(_) => quote! {
+            __modular::macro_support::respond_result::<#codec, _, _>(&*callback, method, #call)
+        },
+    };
+
+    quote! {
+        #name => {
+            #decode
+            #respond
+        }
+    }
+}
+
+fn export(ty: &Type) -> TokenStream2 {
+    quote! {
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        extern "C" fn __wm_create() -> *mut ::wasm_module_core::NativeModule {
+            let module = <#ty as ::std::default::Default>::default();
+            ::std::boxed::Box::into_raw(::std::boxed::Box::new(
+                ::wasm_module_core::NativeModule::new(module),
+            ))
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        #[no_mangle]
+        pub extern "C" fn create_module(
+            registry: ::modular_core::NativeRegistry,
+            recorder: ::native_recorder::NativeBytesRecorder,
+        ) -> ::modular_core::NativeModule {
+            use ::modular_core::NativeAbi;
+
+            let registry = registry.expect_abi();
+            let recorder = recorder.expect_abi();
+
+            // a module loaded again keeps recording with the tracer it registered the
+            // first time
+            let _ = ::native_recorder::register_module_tracer(::std::boxed::Box::leak(
+                ::std::boxed::Box::new(recorder),
+            ));
+
+            ::modular_core::NativeModule::new(<#ty>::new(registry))
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        #[no_mangle]
+        pub extern "C" fn modular_abi() -> ::modular_core::NativeAbiHeader {
+            ::modular_core::NativeModule::abi()
+        }
+    }
+}
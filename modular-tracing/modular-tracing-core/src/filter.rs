@@ -0,0 +1,51 @@
+use protobuf_tracing::types::Record;
+use protobuf_tracing::{Interest, Recorder};
+use std::str::FromStr;
+use tracing::Level;
+
+/// Passes on to `inner` what it is interested in, but for the targets starting with one
+/// of the excluded prefixes and the records more verbose than the maximum level.
+pub struct FilteredRecorder<R> {
+    inner: R,
+    max_level: Level,
+    excluded: Vec<String>,
+}
+
+impl<R: Recorder> FilteredRecorder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            max_level: Level::TRACE,
+            excluded: vec![],
+        }
+    }
+
+    pub fn with_max_level(mut self, level: Level) -> Self {
+        self.max_level = level;
+        self
+    }
+
+    /// Drops the records of targets starting with `prefix`, as `wasmer_` does for wasmer's.
+    pub fn exclude(mut self, prefix: &str) -> Self {
+        self.excluded.push(prefix.to_string());
+        self
+    }
+}
+
+impl<R: Recorder> Recorder for FilteredRecorder<R> {
+    fn is_interested(&self, interest: &Interest) -> bool {
+        !self
+            .excluded
+            .iter()
+            .any(|prefix| interest.target.starts_with(prefix.as_str()))
+            && self.inner.is_interested(interest)
+    }
+
+    fn record(&self, record: &Record) {
+        // the level is only known once recorded; records with an unknown one are kept
+        match Level::from_str(&record.level) {
+            Ok(level) if level > self.max_level => {}
+            _ => self.inner.record(record),
+        }
+    }
+}
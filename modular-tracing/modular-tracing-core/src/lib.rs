@@ -1,3 +1,5 @@
+mod filter;
+
 use ansi_term::Color;
 use native_recorder::{BytesRecorder, NativeBytesRecorder};
 use protobuf_tracing::types::{Record, Value, Values};
@@ -162,4 +164,5 @@ impl FieldsFormat for HashMap<String, Values> {
     }
 }
 
+pub use filter::FilteredRecorder;
 pub use protobuf_tracing::register_module_tracer;
@@ -1,7 +1,8 @@
+use modular_core::{catch_panic, NativeAbi, NativeAbiHeader};
 use protobuf_tracing::types::Record;
 use protobuf_tracing::{Interest, Message};
 use std::mem::ManuallyDrop;
-use std::ptr::null;
+use std::ptr::{null, null_mut};
 
 pub use protobuf_tracing::register_module_tracer;
 pub use protobuf_tracing::Recorder;
@@ -13,6 +14,7 @@ pub trait BytesRecorder: Send + Sync + Clone {
 
 #[repr(C)]
 pub struct NativeBytesRecorder {
+    abi: NativeAbiHeader,
     obj: *const (),
     is_interested: extern "C" fn(
         *const (),
@@ -33,6 +35,7 @@ unsafe impl Sync for NativeBytesRecorder {}
 impl NativeBytesRecorder {
     pub fn new<R: BytesRecorder + 'static>(recorder: R) -> Self {
         Self {
+            abi: NativeAbiHeader::new::<Self>(),
             obj: Box::into_raw(Box::new(recorder)).cast(),
             is_interested: Self::ffi_is_interested::<R>,
             alloc_protobuf_record: Self::ffi_alloc_protobuf_record::<R>,
@@ -59,19 +62,34 @@ impl NativeBytesRecorder {
             None
         };
 
-        let interest = Interest {
-            target: std::str::from_utf8(target).unwrap(),
-            parent_span_name: span_name.map(|i| std::str::from_utf8(i).unwrap()),
-        };
+        // tracing can't be used here: the recorder is the one being traced into
+        let result = catch_panic(|| {
+            let interest = Interest {
+                target: std::str::from_utf8(target).unwrap(),
+                parent_span_name: span_name.map(|i| std::str::from_utf8(i).unwrap()),
+            };
+
+            recorder.is_interested(&interest) as u8
+        });
 
-        recorder.is_interested(&interest) as u8
+        result.unwrap_or_else(|e| {
+            eprintln!("panic in recorder is_interested: {}", e);
+            0
+        })
     }
 
     extern "C" fn ffi_alloc_protobuf_record<R: BytesRecorder>(_: *const (), len: usize) -> *mut u8 {
-        let data = vec![0u8; len];
-        let data = ManuallyDrop::new(data);
+        let result = catch_panic(|| {
+            let data = vec![0u8; len];
+            let data = ManuallyDrop::new(data);
+
+            data.as_ptr() as *mut u8
+        });
 
-        data.as_ptr() as *mut u8
+        result.unwrap_or_else(|e| {
+            eprintln!("panic in recorder alloc_protobuf_record: {}", e);
+            null_mut()
+        })
     }
 
     extern "C" fn ffi_on_protobuf_record<R: BytesRecorder>(
@@ -83,24 +101,45 @@ impl NativeBytesRecorder {
         let recorder = unsafe { &*obj };
 
         let data = unsafe { Vec::from_raw_parts(ptr, len, len) };
-        recorder.record(data)
+
+        if let Err(e) = catch_panic(|| recorder.record(data)) {
+            eprintln!("panic in recorder on_protobuf_record: {}", e);
+        }
     }
 
     extern "C" fn ffi_clone<R: BytesRecorder + 'static>(obj: *const ()) -> NativeBytesRecorder {
         let obj = obj as *const R;
-        let recorder = unsafe { &*obj }.clone();
+        let recorder = unsafe { &*obj };
 
-        Self::new(recorder)
+        match catch_panic(|| recorder.clone()) {
+            Ok(recorder) => Self::new(recorder),
+            Err(e) => {
+                // there is no recorder to hand back, and unwinding further is undefined behavior
+                eprintln!("panic in recorder clone: {}", e);
+                std::process::abort()
+            }
+        }
     }
 
     extern "C" fn ffi_drop<R: BytesRecorder>(obj: *mut ()) {
         if !obj.is_null() {
-            let obj = obj as *mut R;
-            unsafe { Box::from_raw(obj) };
+            let obj = unsafe { Box::from_raw(obj as *mut R) };
+
+            if let Err(e) = catch_panic(|| drop(obj)) {
+                eprintln!("panic in recorder drop: {}", e);
+            }
         }
     }
 }
 
+impl NativeAbi for NativeBytesRecorder {
+    const NAME: &'static str = "NativeBytesRecorder";
+
+    fn abi_header(&self) -> NativeAbiHeader {
+        self.abi
+    }
+}
+
 impl Clone for NativeBytesRecorder {
     fn clone(&self) -> Self {
         (self.clone)(self.obj)
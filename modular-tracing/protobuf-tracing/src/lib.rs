@@ -9,6 +9,7 @@ pub(crate) mod span_fields;
 pub mod types;
 
 pub use prost::*;
+use tracing::subscriber::SetGlobalDefaultError;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry;
 
@@ -16,7 +17,11 @@ pub fn layer<R: Recorder>(r: &'static R) -> ProtobufLayer {
     ProtobufLayer::new(r)
 }
 
-pub fn register_module_tracer<R: Recorder>(recorder: &'static R) {
+/// Records the events of this binary with `recorder`. Fails if a global subscriber is set
+/// already, as when a module is loaded a second time.
+pub fn register_module_tracer<R: Recorder>(
+    recorder: &'static R,
+) -> Result<(), SetGlobalDefaultError> {
     let registry = registry().with(layer(recorder));
-    let _ = tracing::subscriber::set_global_default(registry);
+    tracing::subscriber::set_global_default(registry)
 }
@@ -1,10 +1,11 @@
-use crate::registry_imports::registry_invoke;
+use crate::registry_imports::{registry_invoke, registry_invoke_stream};
 use crate::state::WasmModuleState;
 use crate::utils::{get_uid, read_bytes, read_string};
 use crate::vtable::WasmModuleVTable;
 use modular_core::*;
 use parking_lot::lock_api::MutexGuard;
 use parking_lot::{Mutex, RawMutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tracing::error;
 use uuid::Uuid;
@@ -21,6 +22,9 @@ pub struct WasmModule {
 
     package: String,
     version: String,
+    methods: Vec<MethodDescriptor>,
+    dependencies: Vec<Dependency>,
+    failed: AtomicBool,
 
     state: FunctionEnv<WasmModuleState>,
 }
@@ -52,10 +56,27 @@ impl WasmModule {
         let vtable = WasmModuleVTable::new(&instance, &store)?;
         env.as_mut(&mut store).set_vtable(&vtable);
 
+        // the guest is wasm32, so only the version of its layouts is comparable; checked
+        // before `create`, so that no guest code runs against another layout
+        vtable
+            .abi(&mut store, &memory)?
+            .check_version("NativeModule")?;
+
         let instance_ptr = vtable.create(&mut store)?;
+
+        vtable
+            .abi_header(instance_ptr, &mut store, &memory)?
+            .check_version("NativeModule")?;
+
         let package = vtable.package(instance_ptr, &mut store, &memory)?;
         let version = vtable.version(instance_ptr, &mut store, &memory)?;
 
+        vtable.methods(instance_ptr, &mut store)?;
+        let methods = env.as_mut(&mut store).take_methods();
+
+        vtable.dependencies(instance_ptr, &mut store)?;
+        let dependencies = env.as_mut(&mut store).take_dependencies();
+
         Ok(Self {
             store: Mutex::new(store),
             _instance: instance,
@@ -63,12 +84,47 @@ impl WasmModule {
             instance_ptr,
             version,
             package,
+            methods,
+            dependencies,
+            failed: AtomicBool::new(false),
             memory,
             state: env,
         })
     }
 
-    pub fn invoke(&self, action: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
+    pub fn invoke(
+        &self,
+        action: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.invoke_guest(action, data, callback, token, context)
+    }
+
+    /// The stream callback is only handed to the guest while its `invoke_stream` runs, so
+    /// the guest has to produce the whole stream before returning; the callback is dropped
+    /// then, failing the stream if it didn't complete.
+    pub fn invoke_stream(
+        &self,
+        action: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        self.invoke_guest(action, data, callback, token, context)
+    }
+
+    fn invoke_guest<C: PendingCallback>(
+        &self,
+        action: &str,
+        data: Option<&[u8]>,
+        callback: C,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
         let mut store = self.store.lock();
 
         macro_rules! call {
@@ -76,7 +132,7 @@ impl WasmModule {
                 match $expr {
                     Ok(v) => v,
                     Err(e) => {
-                        callback.on_error(modular_core::CallbackError {
+                        $callback.fail(modular_core::CallbackError {
                             code: $code,
                             err_name: Some($err_name),
                             description: Some(&format!("{:#?}", e)),
@@ -104,6 +160,14 @@ impl WasmModule {
             callback
         );
 
+        let context_ptr = call!(
+            self.vtable
+                .create_native_byte_slice(Some(context.encode()), &mut *store, &self.memory),
+            -10000,
+            "WasmMemError",
+            callback
+        );
+
         let id = Uuid::new_v4();
         let id_ptr = call!(
             self.vtable.write_bytes(&id, &mut *store, &self.memory),
@@ -112,22 +176,33 @@ impl WasmModule {
             callback
         );
 
-        self.state.as_mut(&mut *store).add_callback(id, callback);
-
-        let result =
-            self.vtable
-                .invoke(self.instance_ptr, action_ptr, data_ptr, id_ptr, &mut *store);
+        callback.add(self.state.as_mut(&mut *store), id);
+        self.state.as_mut(&mut *store).add_token(id, token);
+
+        let result = C::invoke(
+            &self.vtable,
+            self.instance_ptr,
+            action_ptr,
+            data_ptr,
+            id_ptr,
+            context_ptr,
+            &mut *store,
+        );
 
-        let callback = self.state.as_mut(&mut *store).remove_callback(&id);
+        let callback = C::remove(self.state.as_mut(&mut *store), &id);
+        self.state.as_mut(&mut *store).remove_token(&id);
 
         if let Err(err) = result {
             error!("Failed to invoke wasm function: {}", err);
-            callback.on_error(CallbackError {
-                code: -10001,
-                err_name: Some("WasmInvokeError"),
-                description: Some(&format!("{:#?}", err)),
-                data: None,
-            });
+
+            if let Some(callback) = callback {
+                callback.fail(CallbackError {
+                    code: -10001,
+                    err_name: Some("WasmInvokeError"),
+                    description: Some(&format!("{:#?}", err)),
+                    data: None,
+                });
+            }
         }
 
         if let Err(err) = self
@@ -144,6 +219,13 @@ impl WasmModule {
             error!("Failed to free native byte slice: {}", err);
         }
 
+        if let Err(err) = self
+            .vtable
+            .free_native_byte_slice(context_ptr, &mut *store, &self.memory)
+        {
+            error!("Failed to free native byte slice: {}", err);
+        }
+
         if let Err(err) = self
             .vtable
             .free(id_ptr, id.as_ref().len() as _, &mut *store)
@@ -152,12 +234,40 @@ impl WasmModule {
         }
     }
 
+    fn lifecycle(
+        &self,
+        hook: &str,
+        f: fn(&WasmModuleVTable, i32, &mut Store) -> anyhow::Result<bool>,
+    ) {
+        let failed = match f(&self.vtable, self.instance_ptr, &mut *self.store.lock()) {
+            Ok(failed) => failed,
+            Err(err) => {
+                error!("Failed to call wasm {} hook: {}", hook, err);
+                true
+            }
+        };
+
+        if failed {
+            error!("module {:?} failed in {}", self.package, hook);
+            self.failed.store(true, Ordering::Release);
+        }
+    }
+
     fn generate_imports(store: &mut Store, function_env: &FunctionEnv<WasmModuleState>) -> Imports {
         imports! {
             "env" => {
                 "__wm_callback_on_success" => Function::new_typed_with_env(store, function_env, on_success_fn),
                 "__wm_callback_on_error" => Function::new_typed_with_env(store, function_env, on_err_fn),
+                "__wm_stream_callback_on_next" => Function::new_typed_with_env(store, function_env, on_next_fn),
+                "__wm_stream_callback_on_complete" => Function::new_typed_with_env(store, function_env, on_complete_fn),
+                "__wm_stream_callback_on_error" => Function::new_typed_with_env(store, function_env, on_stream_err_fn),
+                "__wm_module_method" => Function::new_typed_with_env(store, function_env, method_fn),
+                "__wm_module_dependency" => Function::new_typed_with_env(store, function_env, dependency_fn),
+                "__wm_cancellation_is_cancelled" => Function::new_typed_with_env(store, function_env, is_cancelled_fn),
+                "__wm_cancellation_cancel" => Function::new_typed_with_env(store, function_env, cancel_fn),
+                "__wm_cancellation_remaining" => Function::new_typed_with_env(store, function_env, remaining_fn),
                 "__wm_registry_invoke" => Function::new_typed_with_env(store, function_env, registry_invoke),
+                "__wm_registry_invoke_stream" => Function::new_typed_with_env(store, function_env, registry_invoke_stream),
             }
         }
     }
@@ -201,6 +311,208 @@ fn on_err_fn(
     });
 }
 
+fn on_next_fn(mut env: FunctionEnvMut<WasmModuleState>, ptr: i32, data_ptr: i32, data_len: i32) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    let data = read_bytes(&mem, data_ptr, data_len, &env);
+
+    env.data()
+        .get_stream_callback(&uid)
+        .on_next(CallbackSuccess {
+            data: data.as_deref(),
+        });
+}
+
+fn on_complete_fn(mut env: FunctionEnvMut<WasmModuleState>, ptr: i32) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    env.data().get_stream_callback(&uid).on_complete();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_stream_err_fn(
+    mut env: FunctionEnvMut<WasmModuleState>,
+    ptr: i32,
+    code: i32,
+    err_name_ptr: i32,
+    err_name_len: i32,
+    err_description_ptr: i32,
+    err_description_len: i32,
+    err_data_ptr: i32,
+    err_data_len: i32,
+) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    let err_name = read_string(&mem, err_name_ptr, err_name_len as _, &env);
+    let err_description = read_string(&mem, err_description_ptr, err_description_len as _, &env);
+    let err_data = read_bytes(&mem, err_data_ptr, err_data_len, &env);
+
+    StreamCallback::on_error(
+        &env.data().get_stream_callback(&uid),
+        CallbackError {
+            code,
+            err_name: err_name.as_deref(),
+            description: err_description.as_deref(),
+            data: err_data.as_deref(),
+        },
+    );
+}
+
+fn is_cancelled_fn(mut env: FunctionEnvMut<WasmModuleState>, ptr: i32) -> i32 {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    env.data()
+        .get_token(&uid)
+        .map_or(0, |token| token.is_cancelled() as i32)
+}
+
+fn cancel_fn(mut env: FunctionEnvMut<WasmModuleState>, ptr: i32) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    if let Some(token) = env.data().get_token(&uid) {
+        token.cancel();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn method_fn(
+    mut env: FunctionEnvMut<WasmModuleState>,
+    name_ptr: i32,
+    name_len: i32,
+    description_ptr: i32,
+    description_len: i32,
+    request_schema_ptr: i32,
+    request_schema_len: i32,
+    response_schema_ptr: i32,
+    response_schema_len: i32,
+    streaming: i32,
+    idempotent: i32,
+) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+
+    // the guest passes a null pointer for absent fields
+    let read = |ptr: i32, len: i32| match ptr {
+        0 => None,
+        _ => read_string(&mem, ptr, len as _, &env),
+    };
+
+    let name = read(name_ptr, name_len);
+    let description = read(description_ptr, description_len);
+    let request_schema = read(request_schema_ptr, request_schema_len);
+    let response_schema = read(response_schema_ptr, response_schema_len);
+
+    env.data_mut().push_method(MethodDescriptor {
+        name: name.unwrap_or_default(),
+        description,
+        request_schema,
+        response_schema,
+        streaming: streaming != 0,
+        idempotent: idempotent != 0,
+    });
+}
+
+fn dependency_fn(
+    mut env: FunctionEnvMut<WasmModuleState>,
+    package_ptr: i32,
+    package_len: i32,
+    version_ptr: i32,
+    version_len: i32,
+) {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+
+    let package = read_string(&mem, package_ptr, package_len as _, &env);
+    let version = read_string(&mem, version_ptr, version_len as _, &env);
+
+    env.data_mut().push_dependency(Dependency {
+        package: package.unwrap_or_default(),
+        version: version.unwrap_or_default(),
+    });
+}
+
+/// Nanoseconds left until the deadline, or -1 if there is none.
+fn remaining_fn(mut env: FunctionEnvMut<WasmModuleState>, ptr: i32) -> i64 {
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let uid = get_uid(&mem, ptr, &env);
+
+    env.data()
+        .get_token(&uid)
+        .and_then(|token| token.remaining())
+        .map_or(-1, |d| d.as_nanos().min(i64::MAX as u128) as i64)
+}
+
+/// A host callback handed to the guest for the duration of a single `invoke_guest` call.
+trait PendingCallback: Sized {
+    fn fail(&self, err: CallbackError);
+    fn add(self, state: &mut WasmModuleState, id: Uuid);
+    fn remove(state: &mut WasmModuleState, id: &Uuid) -> Option<Self>;
+    fn invoke(
+        vtable: &WasmModuleVTable,
+        instance: i32,
+        action: i32,
+        data: i32,
+        callback: i32,
+        context: i32,
+        store: &mut Store,
+    ) -> anyhow::Result<()>;
+}
+
+impl PendingCallback for Box<dyn Callback> {
+    fn fail(&self, err: CallbackError) {
+        self.on_error(err)
+    }
+
+    fn add(self, state: &mut WasmModuleState, id: Uuid) {
+        state.add_callback(id, self)
+    }
+
+    fn remove(state: &mut WasmModuleState, id: &Uuid) -> Option<Self> {
+        state.remove_callback(id)
+    }
+
+    fn invoke(
+        vtable: &WasmModuleVTable,
+        instance: i32,
+        action: i32,
+        data: i32,
+        callback: i32,
+        context: i32,
+        store: &mut Store,
+    ) -> anyhow::Result<()> {
+        vtable.invoke(instance, action, data, callback, context, store)
+    }
+}
+
+impl PendingCallback for Box<dyn StreamCallback> {
+    fn fail(&self, err: CallbackError) {
+        self.on_error(err)
+    }
+
+    fn add(self, state: &mut WasmModuleState, id: Uuid) {
+        state.add_stream_callback(id, self)
+    }
+
+    fn remove(state: &mut WasmModuleState, id: &Uuid) -> Option<Self> {
+        state.remove_stream_callback(id)
+    }
+
+    fn invoke(
+        vtable: &WasmModuleVTable,
+        instance: i32,
+        action: i32,
+        data: i32,
+        callback: i32,
+        context: i32,
+        store: &mut Store,
+    ) -> anyhow::Result<()> {
+        vtable.invoke_stream(instance, action, data, callback, context, store)
+    }
+}
+
 impl modular_core::Module for WasmModule {
     fn package(&self) -> &str {
         &self.package
@@ -210,10 +522,99 @@ impl modular_core::Module for WasmModule {
         &self.version
     }
 
+    fn kind(&self) -> ModuleKind {
+        ModuleKind::Wasm
+    }
+
+    fn methods(&self) -> Vec<MethodDescriptor> {
+        self.methods.clone()
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        self.dependencies.clone()
+    }
+
+    fn init(&self) {
+        self.lifecycle("init", WasmModuleVTable::init)
+    }
+
+    fn start(&self) {
+        self.lifecycle("start", WasmModuleVTable::start)
+    }
+
     fn run(&self) {}
 
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
-        WasmModule::invoke(self, method, data, callback)
+    fn stop(&self) {
+        self.lifecycle("stop", WasmModuleVTable::stop)
+    }
+
+    fn shutdown(&self) {
+        self.lifecycle("shutdown", WasmModuleVTable::shutdown)
+    }
+
+    fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        self.vtable
+            .snapshot(self.instance_ptr, &mut *self.store.lock(), &self.memory)
+            .unwrap_or_else(|err| {
+                error!("Failed to call wasm snapshot hook: {}", err);
+                None
+            })
+    }
+
+    fn restore(&self, snapshot: &[u8]) -> Result<(), String> {
+        let restored = self.vtable.restore(
+            self.instance_ptr,
+            snapshot,
+            &mut *self.store.lock(),
+            &self.memory,
+        );
+
+        match restored {
+            Ok(false) => Ok(()),
+            Ok(true) => Err("the guest module failed to restore its snapshot".to_string()),
+            Err(err) => Err(format!("Failed to call wasm restore hook: {}", err)),
+        }
+    }
+
+    fn configure(&self, config: &[u8]) -> Result<(), String> {
+        let configured = self.vtable.configure(
+            self.instance_ptr,
+            config,
+            &mut *self.store.lock(),
+            &self.memory,
+        );
+
+        match configured {
+            Ok(false) => Ok(()),
+            Ok(true) => Err("the guest module failed to apply its configuration".to_string()),
+            Err(err) => Err(format!("Failed to call wasm configure hook: {}", err)),
+        }
+    }
+
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        WasmModule::invoke(self, method, data, callback, token, context)
+    }
+
+    fn invoke_stream(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        token: CancellationToken,
+        context: InvocationContext,
+    ) {
+        WasmModule::invoke_stream(self, method, data, callback, token, context)
     }
 }
 
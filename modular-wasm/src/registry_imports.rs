@@ -1,7 +1,12 @@
 use crate::state::WasmModuleState;
 use crate::utils::{read_bytes, read_string};
-use modular_core::{Callback, CallbackError, CallbackSuccess, Registry};
+use modular_core::{
+    current_deadline, current_invocation, decode_headers, Callback, CallbackError, CallbackSuccess,
+    CancellationToken, Error, InvokeOptions, OwnedError, OwnedSuccess, Registry, StreamCallback,
+};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::thread;
+use std::time::Instant;
 use tracing::error;
 use wasmer::FunctionEnvMut;
 
@@ -10,33 +15,34 @@ struct GuestCallback {
 }
 
 enum CallbackData {
-    Success(Option<Vec<u8>>),
+    Success(OwnedSuccess),
     Error(OwnedError),
 }
 
-struct OwnedError {
-    code: i32,
-    err_name: Option<String>,
-    err_description: Option<String>,
-    err_data: Option<Vec<u8>>,
-}
-
 impl Callback for GuestCallback {
     fn on_success(&self, result: CallbackSuccess) {
-        self.tx
-            .send(CallbackData::Success(result.data.map(|i| i.to_vec())))
-            .unwrap();
+        // the guest stops waiting once the deadline passes
+        let _ = self.tx.send(CallbackData::Success(result.into()));
     }
 
     fn on_error(&self, err: CallbackError) {
-        self.tx
-            .send(CallbackData::Error(OwnedError {
-                code: err.code,
-                err_name: err.err_name.map(|i| i.to_string()),
-                err_description: err.description.map(|i| i.to_string()),
-                err_data: err.data.map(|i| i.to_vec()),
-            }))
-            .unwrap();
+        let _ = self.tx.send(CallbackData::Error(err.into()));
+    }
+}
+
+/// Cancels the invocation `invoke` makes once `cancellation` is, even if that happens
+/// before `invoke` returns its token.
+fn cancel_with(cancellation: &CancellationToken, invoke: impl FnOnce() -> CancellationToken) {
+    let token = invoke();
+    cancellation.on_cancel(move || token.cancel());
+}
+
+/// Waits for the next message, giving up once `deadline` passes. Without one, waits until
+/// the callee completes or drops the callback, as for a native caller.
+fn recv_until<T>(rx: &Receiver<T>, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+    match deadline {
+        Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        None => Ok(rx.recv()?),
     }
 }
 
@@ -49,6 +55,8 @@ pub fn registry_invoke(
     method_len: u32,
     data: i32,
     data_len: u32,
+    headers: i32,
+    headers_len: u32,
     callback_id: i32,
 ) -> i32 {
     let vtable = env.data().get_vtable().clone();
@@ -56,6 +64,9 @@ pub fn registry_invoke(
     let package = read_string(&mem, package, package_len as _, &env);
     let method = read_string(&mem, method, method_len as _, &env);
     let data = read_bytes(&mem, data, data_len as _, &env);
+    let headers = read_bytes(&mem, headers, headers_len as _, &env)
+        .and_then(|h| decode_headers(&h))
+        .unwrap_or_default();
 
     if package.is_none() || method.is_none() {
         match vtable.callback_on_error(
@@ -81,18 +92,42 @@ pub fn registry_invoke(
     let callback = Box::new(GuestCallback { tx });
     let registry = env.data_mut().registry().clone();
 
+    // the call is made from another thread, so the deadline and id of the invocation
+    // running the guest are passed on explicitly
+    let options = InvokeOptions {
+        deadline: current_deadline(),
+        parent: current_invocation(),
+        headers,
+    };
+    let deadline = options.deadline;
+
+    // cancelled once the guest stops waiting for the callee
+    let cancellation = CancellationToken::new();
+    let on_cancel = cancellation.clone();
+
     thread::spawn(move || {
-        registry.invoke(
-            &package.unwrap(),
-            &method.unwrap(),
-            data.as_deref(),
-            callback,
-        );
+        cancel_with(&on_cancel, || {
+            registry.invoke_with(
+                &package.unwrap(),
+                &method.unwrap(),
+                data.as_deref(),
+                callback,
+                options,
+            )
+        });
     });
 
-    match rx.recv() {
-        Ok(CallbackData::Success(data)) => {
-            match vtable.callback_on_success(callback_id, data.as_deref(), &mut env, &mem) {
+    let received = match recv_until(&rx, deadline) {
+        Err(RecvTimeoutError::Timeout) => {
+            cancellation.cancel();
+            Ok(CallbackData::Error(Error::Timeout.into()))
+        }
+        received => received,
+    };
+
+    match received {
+        Ok(CallbackData::Success(result)) => {
+            match vtable.callback_on_success(callback_id, result.data.as_deref(), &mut env, &mem) {
                 Ok(_) => {}
                 Err(err) => {
                     error!("Error calling callback_on_success: {}", err);
@@ -104,8 +139,8 @@ pub fn registry_invoke(
                 callback_id,
                 err.code,
                 err.err_name.as_deref().map(|i| i.as_bytes()),
-                err.err_description.as_deref().map(|i| i.as_bytes()),
-                err.err_data.as_deref(),
+                err.description.as_deref().map(|i| i.as_bytes()),
+                err.data.as_deref(),
                 &mut env,
                 &mem,
             ) {
@@ -124,3 +159,140 @@ pub fn registry_invoke(
 
     0
 }
+
+struct GuestStreamCallback {
+    tx: std::sync::mpsc::Sender<StreamData>,
+}
+
+enum StreamData {
+    Next(OwnedSuccess),
+    Complete,
+    Error(OwnedError),
+}
+
+impl StreamCallback for GuestStreamCallback {
+    fn on_next(&self, result: CallbackSuccess) {
+        let _ = self.tx.send(StreamData::Next(result.into()));
+    }
+
+    fn on_complete(&self) {
+        let _ = self.tx.send(StreamData::Complete);
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        let _ = self.tx.send(StreamData::Error(err.into()));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn registry_invoke_stream(
+    mut env: FunctionEnvMut<WasmModuleState>,
+    package: i32,
+    package_len: u32,
+    method: i32,
+    method_len: u32,
+    data: i32,
+    data_len: u32,
+    headers: i32,
+    headers_len: u32,
+    callback_id: i32,
+) -> i32 {
+    let vtable = env.data().get_vtable().clone();
+    let mem = env.data_mut().get_memory().cloned().unwrap();
+    let package = read_string(&mem, package, package_len as _, &env);
+    let method = read_string(&mem, method, method_len as _, &env);
+    let data = read_bytes(&mem, data, data_len as _, &env);
+    let headers = read_bytes(&mem, headers, headers_len as _, &env)
+        .and_then(|h| decode_headers(&h))
+        .unwrap_or_default();
+
+    if package.is_none() || method.is_none() {
+        if let Err(err) = vtable.stream_callback_on_error(
+            callback_id,
+            -1,
+            Some(b"InvalidArguments"),
+            Some(b"Invalid package or method name"),
+            None,
+            &mut env,
+            &mem,
+        ) {
+            error!("Error calling stream_callback_on_error: {}", err);
+        }
+
+        return -1;
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let callback = Box::new(GuestStreamCallback { tx });
+    let registry = env.data_mut().registry().clone();
+
+    // the call is made from another thread, so the deadline and id of the invocation
+    // running the guest are passed on explicitly
+    let options = InvokeOptions {
+        deadline: current_deadline(),
+        parent: current_invocation(),
+        headers,
+    };
+    let deadline = options.deadline;
+
+    // cancelled once the guest stops waiting for the callee
+    let cancellation = CancellationToken::new();
+    let on_cancel = cancellation.clone();
+
+    thread::spawn(move || {
+        cancel_with(&on_cancel, || {
+            registry.invoke_stream_with(
+                &package.unwrap(),
+                &method.unwrap(),
+                data.as_deref(),
+                callback,
+                options,
+            )
+        });
+    });
+
+    // the guest is single-threaded, so messages are delivered until the stream terminates
+    loop {
+        let message = match recv_until(&rx, deadline) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => {
+                cancellation.cancel();
+                StreamData::Error(Error::Timeout.into())
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let is_terminal = !matches!(message, StreamData::Next(_));
+
+        let result = match message {
+            StreamData::Next(result) => {
+                vtable.stream_callback_on_next(callback_id, result.data.as_deref(), &mut env, &mem)
+            }
+            StreamData::Complete => vtable.stream_callback_on_complete(callback_id, &mut env),
+            StreamData::Error(err) => vtable.stream_callback_on_error(
+                callback_id,
+                err.code,
+                err.err_name.as_deref().map(|i| i.as_bytes()),
+                err.description.as_deref().map(|i| i.as_bytes()),
+                err.data.as_deref(),
+                &mut env,
+                &mem,
+            ),
+        };
+
+        if let Err(err) = result {
+            error!("Error calling stream callback: {}", err);
+        }
+
+        if is_terminal {
+            break;
+        }
+    }
+
+    if let Err(err) = vtable.stream_callback_destroy(callback_id, &mut env) {
+        error!("Error calling stream_callback_destroy: {}", err);
+    }
+
+    0
+}
@@ -1,12 +1,18 @@
-use crate::utils::{OptionalCallback, OptionalCallbackRef};
+use crate::utils::{OptionalCallbackRef, OptionalStreamCallbackRef};
 use crate::vtable::WasmModuleVTable;
-use modular_core::{Callback, NativeRegistry, Registry};
+use modular_core::{
+    Callback, CancellationToken, Dependency, MethodDescriptor, NativeRegistry, Registry, StreamCallback,
+};
 use std::collections::HashMap;
 use uuid::Uuid;
 use wasmer::Memory;
 
 pub struct WasmModuleState {
     callbacks: HashMap<Uuid, Box<dyn Callback>>,
+    stream_callbacks: HashMap<Uuid, Box<dyn StreamCallback>>,
+    tokens: HashMap<Uuid, CancellationToken>,
+    methods: Vec<MethodDescriptor>,
+    dependencies: Vec<Dependency>,
     memory: Option<Memory>,
     registry: NativeRegistry,
     vtable: Option<WasmModuleVTable>,
@@ -16,6 +22,10 @@ impl WasmModuleState {
     pub fn new<R: Registry + 'static>(registry: R) -> Self {
         Self {
             callbacks: HashMap::new(),
+            stream_callbacks: HashMap::new(),
+            tokens: HashMap::new(),
+            methods: vec![],
+            dependencies: vec![],
             memory: None,
             registry: NativeRegistry::new(registry),
             vtable: None,
@@ -46,11 +56,51 @@ impl WasmModuleState {
         self.callbacks.insert(uid, callback);
     }
 
-    pub fn remove_callback(&mut self, uid: &Uuid) -> OptionalCallback {
-        OptionalCallback::new(self.callbacks.remove(uid))
+    pub fn remove_callback(&mut self, uid: &Uuid) -> Option<Box<dyn Callback>> {
+        self.callbacks.remove(uid)
     }
 
     pub fn get_callback(&self, uid: &Uuid) -> OptionalCallbackRef {
         OptionalCallbackRef::new(self.callbacks.get(uid).map(|i| i.as_ref()))
     }
+
+    pub fn add_stream_callback(&mut self, uid: Uuid, callback: Box<dyn StreamCallback>) {
+        self.stream_callbacks.insert(uid, callback);
+    }
+
+    pub fn remove_stream_callback(&mut self, uid: &Uuid) -> Option<Box<dyn StreamCallback>> {
+        self.stream_callbacks.remove(uid)
+    }
+
+    pub fn get_stream_callback(&self, uid: &Uuid) -> OptionalStreamCallbackRef {
+        OptionalStreamCallbackRef::new(self.stream_callbacks.get(uid).map(|i| i.as_ref()))
+    }
+
+    pub fn add_token(&mut self, uid: Uuid, token: CancellationToken) {
+        self.tokens.insert(uid, token);
+    }
+
+    pub fn remove_token(&mut self, uid: &Uuid) -> Option<CancellationToken> {
+        self.tokens.remove(uid)
+    }
+
+    pub fn get_token(&self, uid: &Uuid) -> Option<&CancellationToken> {
+        self.tokens.get(uid)
+    }
+
+    pub fn push_method(&mut self, method: MethodDescriptor) {
+        self.methods.push(method);
+    }
+
+    pub fn take_methods(&mut self) -> Vec<MethodDescriptor> {
+        std::mem::take(&mut self.methods)
+    }
+
+    pub fn push_dependency(&mut self, dependency: Dependency) {
+        self.dependencies.push(dependency);
+    }
+
+    pub fn take_dependencies(&mut self) -> Vec<Dependency> {
+        std::mem::take(&mut self.dependencies)
+    }
 }
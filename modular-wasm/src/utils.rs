@@ -2,14 +2,8 @@ use modular_core::*;
 use uuid::*;
 use wasmer::*;
 
-pub struct OptionalCallback(Option<Box<dyn Callback>>);
 pub struct OptionalCallbackRef<'a>(Option<&'a dyn Callback>);
-
-impl OptionalCallback {
-    pub fn new(callback: Option<Box<dyn Callback>>) -> Self {
-        Self(callback)
-    }
-}
+pub struct OptionalStreamCallbackRef<'a>(Option<&'a dyn StreamCallback>);
 
 impl<'a> OptionalCallbackRef<'a> {
     pub fn new(callback: Option<&'a dyn Callback>) -> Self {
@@ -17,7 +11,7 @@ impl<'a> OptionalCallbackRef<'a> {
     }
 }
 
-impl Callback for OptionalCallback {
+impl Callback for OptionalCallbackRef<'_> {
     fn on_success(&self, result: CallbackSuccess) {
         if let Some(callback) = &self.0 {
             callback.on_success(result);
@@ -31,10 +25,22 @@ impl Callback for OptionalCallback {
     }
 }
 
-impl Callback for OptionalCallbackRef<'_> {
-    fn on_success(&self, result: CallbackSuccess) {
+impl<'a> OptionalStreamCallbackRef<'a> {
+    pub fn new(callback: Option<&'a dyn StreamCallback>) -> Self {
+        Self(callback)
+    }
+}
+
+impl StreamCallback for OptionalStreamCallbackRef<'_> {
+    fn on_next(&self, result: CallbackSuccess) {
         if let Some(callback) = &self.0 {
-            callback.on_success(result);
+            callback.on_next(result);
+        }
+    }
+
+    fn on_complete(&self) {
+        if let Some(callback) = &self.0 {
+            callback.on_complete();
         }
     }
 
@@ -1,3 +1,4 @@
+use modular_core::NativeAbiHeader;
 use wasmer::*;
 
 // Arg1 - instance
@@ -10,15 +11,36 @@ pub struct WasmModuleVTable {
     __wm_alloc: TypedFunction<u32, i32>,
     __wm_free: TypedFunction<(i32, u32), ()>,
 
+    // optional, guests built before the abi handshake don't export it
+    __wm_abi: Option<TypedFunction<(), i32>>,
     __wm_create: TypedFunction<(), i32>,
 
     __wm_module_package: GetStringFunction,
     __wm_module_version: GetStringFunction,
-    __wm_module_invoke: TypedFunction<(i32, i32, i32, i32), ()>,
+    // optional, guests built before method descriptors were introduced don't export it
+    __wm_module_methods: Option<TypedFunction<i32, ()>>,
+    __wm_module_dependencies: Option<TypedFunction<i32, ()>>,
+    // optional as well, and return whether the module is failed
+    __wm_module_init: Option<TypedFunction<i32, i32>>,
+    __wm_module_start: Option<TypedFunction<i32, i32>>,
+    __wm_module_stop: Option<TypedFunction<i32, i32>>,
+    __wm_module_shutdown: Option<TypedFunction<i32, i32>>,
+    // optional too, the first returning whether there is a snapshot and the others whether
+    // the module failed to restore it or to apply its configuration
+    __wm_module_snapshot: Option<TypedFunction<(i32, i32, i32), i32>>,
+    __wm_module_restore: Option<TypedFunction<(i32, i32), i32>>,
+    __wm_module_configure: Option<TypedFunction<(i32, i32), i32>>,
+    __wm_module_invoke: TypedFunction<(i32, i32, i32, i32, i32), ()>,
     __wm_host_callback_on_success: TypedFunction<(i32, i32), ()>,
     __wm_host_callback_on_error: TypedFunction<(i32, i32, i32, i32, i32), ()>,
     __wm_host_callback_destroy: TypedFunction<i32, ()>,
     __wm_module_destroy: TypedFunction<i32, ()>,
+
+    __wm_module_invoke_stream: TypedFunction<(i32, i32, i32, i32, i32), ()>,
+    __wm_host_stream_callback_on_next: TypedFunction<(i32, i32), ()>,
+    __wm_host_stream_callback_on_complete: TypedFunction<i32, ()>,
+    __wm_host_stream_callback_on_error: TypedFunction<(i32, i32, i32, i32, i32), ()>,
+    __wm_host_stream_callback_destroy: TypedFunction<i32, ()>,
 }
 
 // extern "C" fn __wm_host_callback_on_success(callback: &mut NativeCallback, data: NativeByteSlice) {
@@ -31,6 +53,16 @@ pub struct WasmModuleVTable {
 // )
 // extern "C" fn __wm_host_callback_destroy(callback: *mut NativeCallback) {
 // extern "C" fn __wm_module_destroy(module: *mut NativeModule) {
+// extern "C" fn __wm_host_stream_callback_on_next(callback: &mut NativeStreamCallback, data: NativeByteSlice) {
+// extern "C" fn __wm_host_stream_callback_on_complete(callback: &mut NativeStreamCallback) {
+// extern "C" fn __wm_host_stream_callback_on_error(
+//     callback: &mut NativeStreamCallback,
+//     code: i32,
+//     err_name: NativeByteSlice,
+//     err_description: NativeByteSlice,
+//     err_data: NativeByteSlice,
+// )
+// extern "C" fn __wm_host_stream_callback_destroy(callback: *mut NativeStreamCallback) {
 
 impl WasmModuleVTable {
     pub fn new(instance: &Instance, store: &Store) -> anyhow::Result<Self> {
@@ -38,6 +70,7 @@ impl WasmModuleVTable {
             __wm_alloc: instance.exports.get_typed_function(store, "__wm_alloc")?,
             __wm_free: instance.exports.get_typed_function(store, "__wm_free")?,
 
+            __wm_abi: instance.exports.get_typed_function(store, "__wm_abi").ok(),
             __wm_create: instance.exports.get_typed_function(store, "__wm_create")?,
 
             __wm_module_package: instance
@@ -46,6 +79,42 @@ impl WasmModuleVTable {
             __wm_module_version: instance
                 .exports
                 .get_typed_function(store, "__wm_module_version")?,
+            __wm_module_methods: instance
+                .exports
+                .get_typed_function(store, "__wm_module_methods")
+                .ok(),
+            __wm_module_dependencies: instance
+                .exports
+                .get_typed_function(store, "__wm_module_dependencies")
+                .ok(),
+            __wm_module_init: instance
+                .exports
+                .get_typed_function(store, "__wm_module_init")
+                .ok(),
+            __wm_module_start: instance
+                .exports
+                .get_typed_function(store, "__wm_module_start")
+                .ok(),
+            __wm_module_stop: instance
+                .exports
+                .get_typed_function(store, "__wm_module_stop")
+                .ok(),
+            __wm_module_shutdown: instance
+                .exports
+                .get_typed_function(store, "__wm_module_shutdown")
+                .ok(),
+            __wm_module_snapshot: instance
+                .exports
+                .get_typed_function(store, "__wm_module_snapshot")
+                .ok(),
+            __wm_module_restore: instance
+                .exports
+                .get_typed_function(store, "__wm_module_restore")
+                .ok(),
+            __wm_module_configure: instance
+                .exports
+                .get_typed_function(store, "__wm_module_configure")
+                .ok(),
             __wm_module_invoke: instance
                 .exports
                 .get_typed_function(store, "__wm_module_invoke")?,
@@ -61,6 +130,22 @@ impl WasmModuleVTable {
             __wm_module_destroy: instance
                 .exports
                 .get_typed_function(store, "__wm_module_destroy")?,
+
+            __wm_module_invoke_stream: instance
+                .exports
+                .get_typed_function(store, "__wm_module_invoke_stream")?,
+            __wm_host_stream_callback_on_next: instance
+                .exports
+                .get_typed_function(store, "__wm_host_stream_callback_on_next")?,
+            __wm_host_stream_callback_on_complete: instance
+                .exports
+                .get_typed_function(store, "__wm_host_stream_callback_on_complete")?,
+            __wm_host_stream_callback_on_error: instance
+                .exports
+                .get_typed_function(store, "__wm_host_stream_callback_on_error")?,
+            __wm_host_stream_callback_destroy: instance
+                .exports
+                .get_typed_function(store, "__wm_host_stream_callback_destroy")?,
         })
     }
 
@@ -114,6 +199,84 @@ impl WasmModuleVTable {
         Ok(self.__wm_host_callback_destroy.call(store, ptr)?)
     }
 
+    pub fn stream_callback_on_next(
+        &self,
+        callback: i32,
+        data: Option<&[u8]>,
+        store: &mut impl AsStoreMut,
+        memory: &Memory,
+    ) -> anyhow::Result<()> {
+        let data_ptr = self.create_native_byte_slice(data, store, memory)?;
+        self.__wm_host_stream_callback_on_next
+            .call(store, callback, data_ptr)?;
+        self.free_native_byte_slice(data_ptr, store, memory)?;
+        Ok(())
+    }
+
+    pub fn stream_callback_on_complete(
+        &self,
+        callback: i32,
+        store: &mut impl AsStoreMut,
+    ) -> anyhow::Result<()> {
+        Ok(self
+            .__wm_host_stream_callback_on_complete
+            .call(store, callback)?)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn stream_callback_on_error(
+        &self,
+        callback: i32,
+        code: i32,
+        err_name: Option<&[u8]>,
+        err_description: Option<&[u8]>,
+        err_data: Option<&[u8]>,
+        store: &mut impl AsStoreMut,
+        memory: &Memory,
+    ) -> anyhow::Result<()> {
+        let err_name_ptr = self.create_native_byte_slice(err_name, store, memory)?;
+        let err_description_ptr = self.create_native_byte_slice(err_description, store, memory)?;
+        let err_data_ptr = self.create_native_byte_slice(err_data, store, memory)?;
+        self.__wm_host_stream_callback_on_error.call(
+            store,
+            callback,
+            code,
+            err_name_ptr,
+            err_description_ptr,
+            err_data_ptr,
+        )?;
+        self.free_native_byte_slice(err_name_ptr, store, memory)?;
+        self.free_native_byte_slice(err_description_ptr, store, memory)?;
+        self.free_native_byte_slice(err_data_ptr, store, memory)?;
+        Ok(())
+    }
+
+    pub fn stream_callback_destroy(
+        &self,
+        ptr: i32,
+        store: &mut impl AsStoreMut,
+    ) -> anyhow::Result<()> {
+        Ok(self.__wm_host_stream_callback_destroy.call(store, ptr)?)
+    }
+
+    /// Header of the layouts the guest was built with, version 0 for a guest too old to export it.
+    pub fn abi(
+        &self,
+        store: &mut impl AsStoreMut,
+        mem: &Memory,
+    ) -> anyhow::Result<NativeAbiHeader> {
+        match &self.__wm_abi {
+            Some(f) => {
+                let ptr = f.call(store)?;
+                self.abi_header(ptr, store, mem)
+            }
+            None => Ok(NativeAbiHeader {
+                version: 0,
+                size: 0,
+            }),
+        }
+    }
+
     pub fn create(&self, store: &mut impl AsStoreMut) -> anyhow::Result<i32> {
         Ok(self.__wm_create.call(store)?)
     }
@@ -144,17 +307,176 @@ impl WasmModuleVTable {
         self.call_get_string(&self.__wm_module_version, instance, store, mem)
     }
 
+    /// Makes the guest report its methods through the `__wm_module_method` import.
+    pub fn methods(&self, instance: i32, store: &mut Store) -> anyhow::Result<()> {
+        if let Some(methods) = &self.__wm_module_methods {
+            methods.call(store, instance)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the guest report its dependencies through the `__wm_module_dependency` import.
+    pub fn dependencies(&self, instance: i32, store: &mut Store) -> anyhow::Result<()> {
+        if let Some(dependencies) = &self.__wm_module_dependencies {
+            dependencies.call(store, instance)?;
+        }
+        Ok(())
+    }
+
+    pub fn init(&self, instance: i32, store: &mut Store) -> anyhow::Result<bool> {
+        Self::call_lifecycle(&self.__wm_module_init, instance, store)
+    }
+
+    pub fn start(&self, instance: i32, store: &mut Store) -> anyhow::Result<bool> {
+        Self::call_lifecycle(&self.__wm_module_start, instance, store)
+    }
+
+    pub fn stop(&self, instance: i32, store: &mut Store) -> anyhow::Result<bool> {
+        Self::call_lifecycle(&self.__wm_module_stop, instance, store)
+    }
+
+    pub fn shutdown(&self, instance: i32, store: &mut Store) -> anyhow::Result<bool> {
+        Self::call_lifecycle(&self.__wm_module_shutdown, instance, store)
+    }
+
+    /// The snapshot of the guest module, `None` if it has none or doesn't export the hook.
+    pub fn snapshot(
+        &self,
+        instance: i32,
+        store: &mut Store,
+        mem: &Memory,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(snapshot) = &self.__wm_module_snapshot else {
+            return Ok(None);
+        };
+
+        let ptr = self.alloc(4, store)?;
+        let len = self.alloc(4, store)?;
+
+        let has_snapshot = snapshot.call(store, instance, ptr, len)? != 0;
+
+        let (data_ptr, data_len, data) = {
+            let mem_view = mem.view(&store);
+            let data_ptr = WasmPtr::<u32>::new(ptr as _).deref(&mem_view).read()?;
+            let data_len = WasmPtr::<u32>::new(len as _).deref(&mem_view).read()?;
+
+            let data = match has_snapshot {
+                true => Some(
+                    WasmSlice::<u8>::new(&mem_view, data_ptr as _, data_len as _)?.read_to_vec()?,
+                ),
+                false => None,
+            };
+
+            (data_ptr, data_len, data)
+        };
+
+        self.free(ptr, 4, store)?;
+        self.free(len, 4, store)?;
+        if has_snapshot {
+            self.free(data_ptr as i32, data_len, store)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Returns whether the guest module failed to restore `snapshot`; a missing hook is a
+    /// no-op.
+    pub fn restore(
+        &self,
+        instance: i32,
+        snapshot: &[u8],
+        store: &mut Store,
+        mem: &Memory,
+    ) -> anyhow::Result<bool> {
+        self.call_with_bytes(&self.__wm_module_restore, instance, snapshot, store, mem)
+    }
+
+    /// Returns whether the guest module failed to apply `config`; a missing hook is a
+    /// no-op.
+    pub fn configure(
+        &self,
+        instance: i32,
+        config: &[u8],
+        store: &mut Store,
+        mem: &Memory,
+    ) -> anyhow::Result<bool> {
+        self.call_with_bytes(&self.__wm_module_configure, instance, config, store, mem)
+    }
+
+    fn call_with_bytes(
+        &self,
+        f: &Option<TypedFunction<(i32, i32), i32>>,
+        instance: i32,
+        bytes: &[u8],
+        store: &mut Store,
+        mem: &Memory,
+    ) -> anyhow::Result<bool> {
+        let Some(f) = f else {
+            return Ok(false);
+        };
+
+        let bytes_ptr = self.create_native_byte_slice(Some(bytes), store, mem)?;
+        let failed = f.call(store, instance, bytes_ptr)? != 0;
+        self.free_native_byte_slice(bytes_ptr, store, mem)?;
+
+        Ok(failed)
+    }
+
+    /// Returns whether the guest module is failed; a missing hook is a no-op.
+    fn call_lifecycle(
+        f: &Option<TypedFunction<i32, i32>>,
+        instance: i32,
+        store: &mut Store,
+    ) -> anyhow::Result<bool> {
+        match f {
+            Some(f) => Ok(f.call(store, instance)? != 0),
+            None => Ok(false),
+        }
+    }
+
+    /// Reads the `NativeAbiHeader` at `ptr`, the start of a guest `NativeModule`
+    /// or the header `__wm_abi` points to.
+    pub fn abi_header(
+        &self,
+        ptr: i32,
+        store: &mut impl AsStoreMut,
+        mem: &Memory,
+    ) -> anyhow::Result<NativeAbiHeader> {
+        let view = mem.view(&store);
+        let header = WasmSlice::<u32>::new(&view, ptr as u64, 2)?.read_to_vec()?;
+
+        Ok(NativeAbiHeader {
+            version: header[0],
+            size: header[1],
+        })
+    }
+
     pub fn invoke(
         &self,
         instance: i32,
         action: i32,
         data: i32,
         callback: i32,
+        context: i32,
         store: &mut Store,
     ) -> anyhow::Result<()> {
         Ok(self
             .__wm_module_invoke
-            .call(store, instance, action, data, callback)?)
+            .call(store, instance, action, data, callback, context)?)
+    }
+
+    pub fn invoke_stream(
+        &self,
+        instance: i32,
+        action: i32,
+        data: i32,
+        callback: i32,
+        context: i32,
+        store: &mut Store,
+    ) -> anyhow::Result<()> {
+        Ok(self
+            .__wm_module_invoke_stream
+            .call(store, instance, action, data, callback, context)?)
     }
 
     pub fn create_native_byte_slice<B: AsRef<[u8]>>(
@@ -1,5 +1,6 @@
 use wasm_module_core::{
-    registry_invoke, Callback, CallbackError, CallbackSuccess, Module, NativeModule,
+    registry_invoke, Callback, CallbackError, CallbackSuccess, CancellationToken,
+    InvocationContext, Module, NativeModule,
 };
 
 struct WasmModule {}
@@ -15,7 +16,14 @@ impl Module for WasmModule {
 
     fn run(&self) {}
 
-    fn invoke(&self, method: &str, data: Option<&[u8]>, callback: Box<dyn Callback>) {
+    fn invoke(
+        &self,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        _token: CancellationToken,
+        _context: InvocationContext,
+    ) {
         struct TestCallback {}
 
         impl Callback for TestCallback {
@@ -1,8 +1,12 @@
 use modular_core::{
-    get_str, Callback, CallbackError, CallbackSuccess, Module, NativeByteSlice, NativeCallback,
-    NativeModule,
+    encode_headers, get_str, Callback, CallbackError, CallbackSuccess, CancelListener,
+    CancellationSource, CancellationToken, Headers, InvocationContext, Module, NativeByteSlice,
+    NativeCallback, NativeModule, NativeStreamCallback, StreamCallback,
 };
+use std::mem::ManuallyDrop;
 use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::time::Duration;
 
 extern "C" {
     fn __wm_callback_on_success(ptr: i32, data_ptr: *const u8, data_len: usize);
@@ -17,6 +21,43 @@ extern "C" {
         err_data_len: usize,
     );
 
+    fn __wm_stream_callback_on_next(ptr: i32, data_ptr: *const u8, data_len: usize);
+    fn __wm_stream_callback_on_complete(ptr: i32);
+    fn __wm_stream_callback_on_error(
+        ptr: i32,
+        code: i32,
+        err_name: *const u8,
+        err_name_len: usize,
+        err_description: *const u8,
+        err_description_len: usize,
+        err_data: *const u8,
+        err_data_len: usize,
+    );
+
+    fn __wm_module_method(
+        name: *const u8,
+        name_len: usize,
+        description: *const u8,
+        description_len: usize,
+        request_schema: *const u8,
+        request_schema_len: usize,
+        response_schema: *const u8,
+        response_schema_len: usize,
+        streaming: i32,
+        idempotent: i32,
+    );
+
+    fn __wm_module_dependency(
+        package: *const u8,
+        package_len: usize,
+        version: *const u8,
+        version_len: usize,
+    );
+
+    fn __wm_cancellation_is_cancelled(ptr: i32) -> i32;
+    fn __wm_cancellation_cancel(ptr: i32);
+    fn __wm_cancellation_remaining(ptr: i32) -> i64;
+
     fn __wm_registry_invoke(
         package: *const u8,
         package_len: usize,
@@ -24,8 +65,72 @@ extern "C" {
         method_len: usize,
         data: *const u8,
         data_len: usize,
+        headers: *const u8,
+        headers_len: usize,
         callback_id: i32,
     ) -> i32;
+
+    fn __wm_registry_invoke_stream(
+        package: *const u8,
+        package_len: usize,
+        method: *const u8,
+        method_len: usize,
+        data: *const u8,
+        data_len: usize,
+        headers: *const u8,
+        headers_len: usize,
+        callback_id: i32,
+    ) -> i32;
+}
+
+/// Token of a host invocation, keyed by the same id as its callback. The guest can't be
+/// interrupted, so `on_cancel` listeners fire only once a poll observes the cancellation.
+struct WasmCancellation {
+    callback_id: i32,
+    listeners: Mutex<Vec<CancelListener>>,
+}
+
+impl WasmCancellation {
+    fn token(callback_id: i32) -> CancellationToken {
+        CancellationToken::from_source(Self {
+            callback_id,
+            listeners: Mutex::new(vec![]),
+        })
+    }
+
+    fn notify(&self) {
+        let listeners = std::mem::take(&mut *self.listeners.lock().unwrap());
+        for listener in listeners {
+            listener();
+        }
+    }
+}
+
+impl CancellationSource for WasmCancellation {
+    fn is_cancelled(&self) -> bool {
+        let cancelled = unsafe { __wm_cancellation_is_cancelled(self.callback_id) != 0 };
+
+        if cancelled {
+            self.notify();
+        }
+
+        cancelled
+    }
+
+    fn cancel(&self) {
+        unsafe { __wm_cancellation_cancel(self.callback_id) };
+        self.notify();
+    }
+
+    fn on_cancel(&self, listener: CancelListener) {
+        self.listeners.lock().unwrap().push(listener);
+        self.is_cancelled();
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        let nanos = unsafe { __wm_cancellation_remaining(self.callback_id) };
+        (nanos >= 0).then(|| Duration::from_nanos(nanos as u64))
+    }
 }
 
 pub fn registry_invoke<C: Callback + 'static>(
@@ -34,6 +139,19 @@ pub fn registry_invoke<C: Callback + 'static>(
     data: Option<&[u8]>,
     callback: C,
 ) -> i32 {
+    registry_invoke_with_headers(package, method, data, &Headers::new(), callback)
+}
+
+/// `registry_invoke` passing `headers` along in the context of the invocation. The caller
+/// and the parent invocation are filled in by the host.
+pub fn registry_invoke_with_headers<C: Callback + 'static>(
+    package: &str,
+    method: &str,
+    data: Option<&[u8]>,
+    headers: &Headers,
+    callback: C,
+) -> i32 {
+    let headers = encode_headers(headers);
     let callback = Box::into_raw(Box::new(NativeCallback::new(callback)));
     unsafe {
         __wm_registry_invoke(
@@ -43,6 +161,41 @@ pub fn registry_invoke<C: Callback + 'static>(
             method.len(),
             data.map(|i| i.as_ptr()).unwrap_or(null_mut()),
             data.map(|i| i.len()).unwrap_or(0),
+            headers.as_ptr(),
+            headers.len(),
+            callback as i32,
+        )
+    }
+}
+
+pub fn registry_invoke_stream<C: StreamCallback + 'static>(
+    package: &str,
+    method: &str,
+    data: Option<&[u8]>,
+    callback: C,
+) -> i32 {
+    registry_invoke_stream_with_headers(package, method, data, &Headers::new(), callback)
+}
+
+pub fn registry_invoke_stream_with_headers<C: StreamCallback + 'static>(
+    package: &str,
+    method: &str,
+    data: Option<&[u8]>,
+    headers: &Headers,
+    callback: C,
+) -> i32 {
+    let headers = encode_headers(headers);
+    let callback = Box::into_raw(Box::new(NativeStreamCallback::new(callback)));
+    unsafe {
+        __wm_registry_invoke_stream(
+            package.as_ptr(),
+            package.len(),
+            method.as_ptr(),
+            method.len(),
+            data.map(|i| i.as_ptr()).unwrap_or(null_mut()),
+            data.map(|i| i.len()).unwrap_or(0),
+            headers.as_ptr(),
+            headers.len(),
             callback as i32,
         )
     }
@@ -84,6 +237,53 @@ extern "C" fn __wm_host_callback_destroy(callback: *mut NativeCallback) {
     }
 }
 
+#[no_mangle]
+extern "C" fn __wm_host_stream_callback_on_next(
+    callback: &mut NativeStreamCallback,
+    data: NativeByteSlice,
+) {
+    let data: Option<&[u8]> = data.into();
+    callback.on_next(CallbackSuccess { data });
+}
+
+#[no_mangle]
+extern "C" fn __wm_host_stream_callback_on_complete(callback: &mut NativeStreamCallback) {
+    callback.on_complete();
+}
+
+#[no_mangle]
+extern "C" fn __wm_host_stream_callback_on_error(
+    callback: &mut NativeStreamCallback,
+    code: i32,
+    err_name: NativeByteSlice,
+    err_description: NativeByteSlice,
+    err_data: NativeByteSlice,
+) {
+    let err_name = Option::<&[u8]>::from(err_name).map(|i| String::from_utf8_lossy(i).to_string());
+    let err_description =
+        Option::<&[u8]>::from(err_description).map(|i| String::from_utf8_lossy(i).to_string());
+    let err_data: Option<&[u8]> = err_data.into();
+
+    StreamCallback::on_error(
+        callback,
+        CallbackError {
+            code,
+            err_name: err_name.as_deref(),
+            description: err_description.as_deref(),
+            data: err_data,
+        },
+    );
+}
+
+#[no_mangle]
+extern "C" fn __wm_host_stream_callback_destroy(callback: *mut NativeStreamCallback) {
+    if !callback.is_null() {
+        unsafe {
+            drop(Box::from_raw(callback));
+        }
+    }
+}
+
 #[no_mangle]
 extern "C" fn __wm_module_package(module: &NativeModule, dest: &mut *const u8, len: &mut usize) {
     *dest = module.package().as_ptr();
@@ -96,15 +296,120 @@ extern "C" fn __wm_module_version(module: &NativeModule, dest: &mut *const u8, l
     *len = module.version().len();
 }
 
+#[no_mangle]
+extern "C" fn __wm_module_methods(module: &NativeModule) {
+    fn ptr(s: &Option<String>) -> *const u8 {
+        s.as_ref().map(|i| i.as_ptr()).unwrap_or(null_mut())
+    }
+
+    fn len(s: &Option<String>) -> usize {
+        s.as_ref().map(|i| i.len()).unwrap_or(0)
+    }
+
+    for method in module.methods() {
+        unsafe {
+            __wm_module_method(
+                method.name.as_ptr(),
+                method.name.len(),
+                ptr(&method.description),
+                len(&method.description),
+                ptr(&method.request_schema),
+                len(&method.request_schema),
+                ptr(&method.response_schema),
+                len(&method.response_schema),
+                method.streaming as i32,
+                method.idempotent as i32,
+            )
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn __wm_module_dependencies(module: &NativeModule) {
+    for dependency in module.dependencies() {
+        unsafe {
+            __wm_module_dependency(
+                dependency.package.as_ptr(),
+                dependency.package.len(),
+                dependency.version.as_ptr(),
+                dependency.version.len(),
+            )
+        }
+    }
+}
+
+/// The lifecycle exports return whether the module is failed after the hook.
+#[no_mangle]
+extern "C" fn __wm_module_init(module: &NativeModule) -> i32 {
+    module.init();
+    module.is_failed() as i32
+}
+
+#[no_mangle]
+extern "C" fn __wm_module_start(module: &NativeModule) -> i32 {
+    module.start();
+    module.is_failed() as i32
+}
+
+#[no_mangle]
+extern "C" fn __wm_module_stop(module: &NativeModule) -> i32 {
+    module.stop();
+    module.is_failed() as i32
+}
+
+#[no_mangle]
+extern "C" fn __wm_module_shutdown(module: &NativeModule) -> i32 {
+    module.shutdown();
+    module.is_failed() as i32
+}
+
+/// Returns whether the module has a snapshot, handed over in memory the host releases
+/// with `__wm_free`.
+#[no_mangle]
+extern "C" fn __wm_module_snapshot(
+    module: &NativeModule,
+    dest: &mut *const u8,
+    len: &mut usize,
+) -> i32 {
+    match module.snapshot() {
+        Some(snapshot) => {
+            let snapshot = ManuallyDrop::new(snapshot.into_boxed_slice());
+            *dest = snapshot.as_ptr();
+            *len = snapshot.len();
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Returns whether the module failed to restore `snapshot`.
+#[no_mangle]
+extern "C" fn __wm_module_restore(module: &NativeModule, snapshot: NativeByteSlice) -> i32 {
+    let snapshot = Option::<&[u8]>::from(snapshot).unwrap_or_default();
+    module.restore(snapshot).is_err() as i32
+}
+
+/// Returns whether the module failed to apply `config`.
+#[no_mangle]
+extern "C" fn __wm_module_configure(module: &NativeModule, config: NativeByteSlice) -> i32 {
+    let config = Option::<&[u8]>::from(config).unwrap_or_default();
+    module.configure(config).is_err() as i32
+}
+
 #[no_mangle]
 extern "C" fn __wm_module_invoke(
     module: &NativeModule,
     method: NativeByteSlice,
     data: NativeByteSlice,
     callback_id: i32,
+    context: NativeByteSlice,
 ) {
     let method = get_str!(method, method);
     let data: Option<&[u8]> = data.into();
+    // encoded with `InvocationContext::encode` by the host
+    let context = Option::<&[u8]>::from(context)
+        .and_then(InvocationContext::decode)
+        .unwrap_or_default();
 
     struct WasmCallback {
         callback_id: i32,
@@ -141,7 +446,79 @@ extern "C" fn __wm_module_invoke(
         }
     }
 
-    module.invoke(method, data, Box::new(WasmCallback { callback_id }))
+    module.invoke(
+        method,
+        data,
+        Box::new(WasmCallback { callback_id }),
+        WasmCancellation::token(callback_id),
+        context,
+    )
+}
+
+#[no_mangle]
+extern "C" fn __wm_module_invoke_stream(
+    module: &NativeModule,
+    method: NativeByteSlice,
+    data: NativeByteSlice,
+    callback_id: i32,
+    context: NativeByteSlice,
+) {
+    let method = get_str!(method, method);
+    let data: Option<&[u8]> = data.into();
+    // encoded with `InvocationContext::encode` by the host
+    let context = Option::<&[u8]>::from(context)
+        .and_then(InvocationContext::decode)
+        .unwrap_or_default();
+
+    struct WasmStreamCallback {
+        callback_id: i32,
+    }
+
+    impl StreamCallback for WasmStreamCallback {
+        fn on_next(&self, result: CallbackSuccess) {
+            unsafe {
+                __wm_stream_callback_on_next(
+                    self.callback_id,
+                    result.data.map(|i| i.as_ptr()).unwrap_or(null_mut()),
+                    result.data.map(|i| i.len()).unwrap_or(0),
+                );
+            }
+        }
+
+        fn on_complete(&self) {
+            unsafe {
+                __wm_stream_callback_on_complete(self.callback_id);
+            }
+        }
+
+        fn on_error(&self, err: CallbackError) {
+            unsafe {
+                __wm_stream_callback_on_error(
+                    self.callback_id,
+                    err.code,
+                    err.err_name
+                        .map(|i| i.as_bytes().as_ptr())
+                        .unwrap_or(null_mut()),
+                    err.err_name.map(|i| i.len()).unwrap_or(0),
+                    err.description
+                        .map(|i| i.as_bytes().as_ptr())
+                        .unwrap_or(null_mut()),
+                    err.description.map(|i| i.len()).unwrap_or(0),
+                    err.data.map(|i| i.as_ptr()).unwrap_or(null_mut()),
+                    err.data.map(|i| i.len()).unwrap_or(0),
+                )
+            }
+        }
+    }
+
+    // the host drops the callback once this returns, so the stream can't outlive the call
+    module.invoke_stream(
+        method,
+        data,
+        Box::new(WasmStreamCallback { callback_id }),
+        WasmCancellation::token(callback_id),
+        context,
+    )
 }
 
 #[no_mangle]
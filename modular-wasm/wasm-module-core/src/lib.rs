@@ -4,6 +4,14 @@ pub use fn_mappings::*;
 pub use modular_core::*;
 use std::mem::ManuallyDrop;
 
+static ABI: NativeAbiHeader = NativeModule::abi();
+
+/// Checked by the host before `__wm_create`, as `ABI_SYMBOL` is for a dll module.
+#[no_mangle]
+extern "C" fn __wm_abi() -> *const NativeAbiHeader {
+    &ABI
+}
+
 #[no_mangle]
 extern "C" fn __wm_alloc(len: usize) -> *mut u8 {
     let v = vec![0; len];
@@ -0,0 +1,212 @@
+use crate::modular::ModularEntity;
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The registered versions of one package.
+pub(crate) type Versions = BTreeMap<Version, Replicas>;
+
+/// The modules registered for one version of a package, several if it was registered
+/// again under `DuplicatePolicy::Replicate`. Invocations go to them in turn.
+#[derive(Clone)]
+pub(crate) struct Replicas {
+    modules: Vec<(String, ModularEntity)>,
+    // replicas registered so far, so that ids aren't reused after one is removed
+    registered: usize,
+    next: Arc<AtomicUsize>,
+}
+
+impl Replicas {
+    pub fn new(id: String, module: ModularEntity) -> Self {
+        Self {
+            modules: vec![(id, module)],
+            registered: 1,
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Adds a replica, returning its id: `id` followed by `#` and the number of the replica.
+    pub fn push(&mut self, id: &str, module: ModularEntity) -> String {
+        let id = format!("{}#{}", id, self.registered);
+        self.registered += 1;
+        self.modules.push((id.clone(), module));
+        id
+    }
+
+    /// Removes every replica, leaving ids to continue from the last one so that those of
+    /// replicas pushed afterwards don't collide with the ones taken.
+    pub fn take(&mut self) -> Vec<(String, ModularEntity)> {
+        std::mem::take(&mut self.modules)
+    }
+
+    /// The replica the next invocation goes to.
+    pub fn pick(&self) -> &(String, ModularEntity) {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.modules[next % self.modules.len()]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, ModularEntity)> {
+        self.modules.iter()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.modules.iter().map(|(id, _)| id.clone()).collect()
+    }
+}
+
+/// A module address, `package` or `package@requirement` as in `dll.module2@^1.2`.
+pub(crate) struct Address<'a> {
+    pub package: &'a str,
+    pub version: Option<VersionReq>,
+}
+
+impl<'a> Address<'a> {
+    pub fn parse(address: &'a str) -> Result<Self, String> {
+        let (package, version) = match address.split_once('@') {
+            Some((package, version)) => (package, Some(version)),
+            None => (address, None),
+        };
+
+        let version = version
+            .map(VersionReq::parse)
+            .transpose()
+            .map_err(|e| format!("invalid version requirement in {:?}: {}", address, e))?;
+
+        Ok(Self { package, version })
+    }
+
+    /// The highest of `versions` the address matches. Without a requirement that is the
+    /// highest version registered, pre-release or not.
+    pub fn resolve<'v>(&self, versions: &'v Versions) -> Option<(&'v Version, &'v Replicas)> {
+        versions
+            .iter()
+            .rev()
+            .find(|(v, _)| self.version.as_ref().is_none_or(|req| req.matches(v)))
+    }
+}
+
+/// Identifies one registered version of a package, in states, reports and logs.
+pub(crate) fn module_id(package: &str, version: &Version) -> String {
+    format!("{}@{}", package, version)
+}
+
+/// The package and version of a module id, as made by `module_id` and `Replicas::push`.
+pub(crate) fn split_id(id: &str) -> (&str, &str) {
+    let (package, version) = id.split_once('@').unwrap_or((id, ""));
+    (package, version.split_once('#').map_or(version, |(v, _)| v))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use modular_core::{Callback, CancellationToken, InvocationContext, Module};
+    use parking_lot::RwLock;
+
+    struct Stub;
+
+    impl Module for Stub {
+        fn package(&self) -> &str {
+            "stub"
+        }
+
+        fn version(&self) -> &str {
+            "0.0.0"
+        }
+
+        fn run(&self) {}
+
+        fn invoke(
+            &self,
+            _method: &str,
+            _data: Option<&[u8]>,
+            _callback: Box<dyn Callback>,
+            _token: CancellationToken,
+            _context: InvocationContext,
+        ) {
+        }
+    }
+
+    pub(crate) fn entity() -> ModularEntity {
+        Arc::new(RwLock::new(Box::new(Stub)))
+    }
+
+    /// `versions` of `package` registered once each.
+    pub(crate) fn versions(package: &str, versions: &[&str]) -> Versions {
+        versions
+            .iter()
+            .map(|v| {
+                let version = Version::parse(v).unwrap();
+                let id = module_id(package, &version);
+                (version, Replicas::new(id, entity()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_splits_the_requirement() {
+        let address = Address::parse("dll.module2@^1.2").unwrap();
+        assert_eq!(address.package, "dll.module2");
+        assert_eq!(address.version, Some(VersionReq::parse("^1.2").unwrap()));
+
+        let address = Address::parse("dll.module2").unwrap();
+        assert_eq!(address.package, "dll.module2");
+        assert_eq!(address.version, None);
+    }
+
+    #[test]
+    fn parse_rejects_an_invalid_requirement() {
+        assert!(Address::parse("dll.module2@not a version").is_err());
+    }
+
+    #[test]
+    fn resolve_picks_the_highest_matching_version() {
+        let versions = versions("p", &["1.0.0", "1.2.0", "2.0.0-beta.1", "2.0.0"]);
+        let resolve = |address| {
+            Address::parse(address)
+                .unwrap()
+                .resolve(&versions)
+                .map(|(v, _)| v.to_string())
+        };
+
+        assert_eq!(resolve("p@^1").as_deref(), Some("1.2.0"));
+        assert_eq!(resolve("p@~1.0").as_deref(), Some("1.0.0"));
+        assert_eq!(resolve("p").as_deref(), Some("2.0.0"));
+    }
+
+    #[test]
+    fn resolve_finds_nothing_without_a_matching_version() {
+        let versions = versions("p", &["1.0.0", "1.2.0"]);
+
+        let address = Address::parse("p@^2").unwrap();
+        assert!(address.resolve(&versions).is_none());
+    }
+
+    #[test]
+    fn pick_goes_round_robin() {
+        let mut replicas = Replicas::new("p@1.0.0".to_string(), entity());
+        replicas.push("p@1.0.0", entity());
+        replicas.push("p@1.0.0", entity());
+
+        let picked = (0..6)
+            .map(|_| replicas.pick().0.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            picked,
+            [
+                "p@1.0.0",
+                "p@1.0.0#1",
+                "p@1.0.0#2",
+                "p@1.0.0",
+                "p@1.0.0#1",
+                "p@1.0.0#2"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_id_drops_the_replica_number() {
+        assert_eq!(split_id("p@1.0.0#2"), ("p", "1.0.0"));
+        assert_eq!(split_id("p@1.0.0"), ("p", "1.0.0"));
+    }
+}
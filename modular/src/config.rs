@@ -0,0 +1,233 @@
+use crate::{
+    Access, DuplicatePolicy, LoadError, LoadReport, Manifest, Modular, OperationRule, Permissions,
+    RestorePolicy, Rule,
+};
+use modular_tracing_core::{
+    register_module_tracer, DefaultRecorder, FilteredRecorder, LazyBytesRecorder, LazyRecorder,
+};
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{error, warn, Level};
+
+/// Describes a whole deployment for `Modular::from_config`, e.g.
+///
+/// ```toml
+/// directories = ["modules"]
+///
+/// [tracing]
+/// level = "info"
+/// exclude = ["wasmer_"]
+///
+/// [registry]
+/// duplicate_policy = "replace"
+/// restore_policy = "keep_old"
+/// drain_timeout_ms = 5000
+/// default_access = "deny"
+///
+/// [[registry.rules]]
+/// caller = "dll.module1"
+/// package = "dll.module2"
+/// method = "*"
+/// access = "allow"
+///
+/// [[registry.operations]]
+/// caller = "dll.module1"
+/// package = "dll.module2"
+/// operation = "deregister"
+///
+/// [[modules]]
+/// kind = "dll"
+/// path = "target/debug/libmodule1.so"
+/// package = "dll.module1"
+/// version = "^1"
+/// config = { greeting = "hello" }
+/// ```
+///
+/// Paths are relative to the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    #[serde(default)]
+    pub registry: RegistryConfig,
+    /// Loaded as by `Modular::load_directory`, after `modules`.
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+    /// Manifests of the modules to load, as in a `module.toml`.
+    #[serde(default)]
+    pub modules: Vec<Manifest>,
+}
+
+impl HostConfig {
+    /// Reads the host config at `path`, resolving its paths relative to it.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let mut config: Self =
+            toml::from_str(&fs::read_to_string(path)?).map_err(LoadError::Config)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for directory in &mut config.directories {
+            *directory = dir.join(&*directory);
+        }
+        config.modules = config
+            .modules
+            .into_iter()
+            .map(|manifest| manifest.relative_to(path))
+            .collect();
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConfig {
+    /// The most verbose level recorded, all of them if `None`.
+    #[serde(default, deserialize_with = "deserialize_level")]
+    pub level: Option<Level>,
+    /// Prefixes of the targets not recorded.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// The recorder `TracingConfig::install` installed.
+static INSTALLED: OnceLock<LazyBytesRecorder> = OnceLock::new();
+
+impl TracingConfig {
+    /// Records the events of the host and of its modules with a `DefaultRecorder`, filtered
+    /// as configured, returning the recorder to hand to modules. Tracing is installed once
+    /// per process: later calls return the recorder of the first, whatever their config.
+    /// If the host set a global subscriber of its own, only the events of modules are
+    /// recorded.
+    pub fn install(&self) -> LazyBytesRecorder {
+        INSTALLED
+            .get_or_init(|| {
+                let recorder = self.exclude.iter().fold(
+                    FilteredRecorder::new(DefaultRecorder::new()),
+                    |r, prefix| r.exclude(prefix),
+                );
+                let recorder = match self.level {
+                    Some(level) => recorder.with_max_level(level),
+                    None => recorder,
+                };
+
+                let recorder = Box::leak(Box::new(recorder));
+                let (runner, receiver) = LazyRecorder::new(recorder);
+                if let Err(e) = register_module_tracer(recorder) {
+                    warn!("not recording the events of the host: {}", e);
+                }
+                runner.run();
+
+                receiver
+            })
+            .clone()
+    }
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Level>, D::Error> {
+    let level = String::deserialize(deserializer)?;
+    Level::from_str(&level)
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("invalid level {:?}", level)))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    #[serde(default)]
+    pub duplicate_policy: DuplicatePolicy,
+    #[serde(default)]
+    pub restore_policy: RestorePolicy,
+    /// See `Modular::with_drain_timeout`.
+    pub drain_timeout_ms: Option<u64>,
+    /// Access of the calls matching none of `rules`.
+    #[serde(default = "allow")]
+    pub default_access: Access,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Operations on the registry modules are granted, none by default.
+    #[serde(default)]
+    pub operations: Vec<OperationRule>,
+}
+
+impl Default for RegistryConfig {
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            restore_policy: RestorePolicy::default(),
+            drain_timeout_ms: None,
+            default_access: allow(),
+            rules: vec![],
+            operations: vec![],
+        }
+    }
+}
+
+fn allow() -> Access {
+    Access::Allow
+}
+
+impl RegistryConfig {
+    /// A registry with the configured policies.
+    pub fn build(&self) -> Modular {
+        let permissions = match self.default_access {
+            Access::Allow => Permissions::allow_all(),
+            Access::Deny => Permissions::deny_all(),
+        };
+        let permissions = self.rules.iter().fold(permissions, |p, r| {
+            p.rule(&r.caller, &r.package, &r.method, r.access)
+        });
+        let permissions = self.operations.iter().fold(permissions, |p, r| {
+            p.allow_operation(&r.caller, &r.package, r.operation)
+        });
+
+        let modular = Modular::default()
+            .with_duplicate_policy(self.duplicate_policy)
+            .with_restore_policy(self.restore_policy)
+            .with_permissions(permissions);
+
+        match self.drain_timeout_ms {
+            Some(ms) => modular.with_drain_timeout(Duration::from_millis(ms)),
+            None => modular,
+        }
+    }
+}
+
+impl Modular {
+    /// Boots the deployment the host config at `path` describes: sets up tracing for the
+    /// host and its modules, builds the registry and loads the modules. One module failing
+    /// to load doesn't stop the others; failures are logged and reported. Fails only if the
+    /// config can't be read.
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<(Self, LoadReport), LoadError> {
+        let config = HostConfig::read(path)?;
+        let recorder = config.tracing.install();
+        let modular = config.registry.build();
+        let mut report = LoadReport::default();
+
+        for manifest in config.modules {
+            let path = manifest.path.clone();
+            match modular.register_manifest(manifest, recorder.clone()) {
+                Ok(package) => report.loaded.push(package),
+                Err(e) => {
+                    error!("failed to load module {}: {}", path.display(), e);
+                    report.failed.push((path, e));
+                }
+            }
+        }
+
+        for directory in &config.directories {
+            let loaded = modular.load_directory(directory, recorder.clone());
+            report.loaded.extend(loaded.loaded);
+            report.failed.extend(loaded.failed);
+        }
+
+        Ok((modular, report))
+    }
+}
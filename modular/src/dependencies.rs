@@ -0,0 +1,199 @@
+use crate::address::{Address, Versions};
+use modular_core::Dependency;
+use semver::VersionReq;
+use std::collections::{BTreeMap, HashMap};
+
+/// Ids of modules mapped to the ids of the registered modules they depend on.
+pub(crate) type DependencyGraph = HashMap<String, Vec<String>>;
+
+pub(crate) struct Unmet {
+    pub reason: String,
+    /// Whether the dependency is only missing, so registering it later can still meet it.
+    pub missing: bool,
+}
+
+impl Unmet {
+    fn new(reason: String) -> Self {
+        Self {
+            reason,
+            missing: false,
+        }
+    }
+}
+
+/// Ids of the replicas of the registered module meeting `dependency`, the highest version
+/// if there are several.
+pub(crate) fn resolve(
+    dependency: &Dependency,
+    modules: &HashMap<String, Versions>,
+) -> Result<Vec<String>, Unmet> {
+    let req = VersionReq::parse(&dependency.version).map_err(|e| {
+        Unmet::new(format!(
+            "invalid version requirement {:?} for {:?}: {}",
+            dependency.version, dependency.package, e
+        ))
+    })?;
+
+    let versions = modules.get(&dependency.package).ok_or_else(|| Unmet {
+        reason: format!("{:?} is not registered", dependency.package),
+        missing: true,
+    })?;
+
+    let address = Address {
+        package: &dependency.package,
+        version: Some(req),
+    };
+
+    match address.resolve(versions) {
+        Some((_, replicas)) => Ok(replicas.ids()),
+        None => Err(Unmet::new(format!(
+            "no registered version of {:?} matches {} (registered: {})",
+            dependency.package,
+            dependency.version,
+            versions
+                .keys()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Orders the modules of `graph` so each comes after the modules it depends on, which
+/// are expected to be in `graph` as well. Fails with the modules of a cycle if there is one.
+pub(crate) fn start_order(
+    graph: &HashMap<String, Vec<String>>,
+) -> Result<Vec<String>, Vec<String>> {
+    #[derive(Copy, Clone, Eq, PartialEq)]
+    enum Mark {
+        Visiting,
+        Done,
+    }
+
+    fn visit<'a>(
+        package: &'a str,
+        graph: &BTreeMap<&'a str, Vec<&'a str>>,
+        marks: &mut HashMap<&'a str, Mark>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<String>,
+    ) -> Result<(), Vec<String>> {
+        match marks.get(package) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                let start = path.iter().position(|p| *p == package).unwrap_or(0);
+                let mut cycle = path[start..]
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>();
+                cycle.push(package.to_string());
+                return Err(cycle);
+            }
+            None => {}
+        }
+
+        marks.insert(package, Mark::Visiting);
+        path.push(package);
+
+        for dependency in graph.get(package).into_iter().flatten() {
+            visit(dependency, graph, marks, path, order)?;
+        }
+
+        path.pop();
+        marks.insert(package, Mark::Done);
+        order.push(package.to_string());
+
+        Ok(())
+    }
+
+    // sorted, so that the order doesn't change from run to run
+    let graph = graph
+        .iter()
+        .map(|(package, dependencies)| {
+            let mut dependencies = dependencies.iter().map(String::as_str).collect::<Vec<_>>();
+            dependencies.sort_unstable();
+            (package.as_str(), dependencies)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut marks = HashMap::new();
+    let mut order = vec![];
+
+    for package in graph.keys() {
+        visit(package, &graph, &mut marks, &mut vec![], &mut order)?;
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::tests::versions;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        edges
+            .iter()
+            .map(|(id, dependencies)| {
+                let dependencies = dependencies.iter().map(|d| d.to_string()).collect();
+                (id.to_string(), dependencies)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn start_order_puts_dependencies_first() {
+        let graph = graph(&[("x", &["z"]), ("z", &["y"]), ("y", &[]), ("a", &["y", "x"])]);
+
+        assert_eq!(start_order(&graph).unwrap(), ["y", "z", "x", "a"]);
+    }
+
+    #[test]
+    fn start_order_is_deterministic() {
+        let edges: &[(&str, &[&str])] = &[("c", &["b", "a"]), ("b", &[]), ("a", &[]), ("d", &[])];
+        let reversed = edges.iter().rev().cloned().collect::<Vec<_>>();
+
+        let order = start_order(&graph(edges)).unwrap();
+        assert_eq!(order, ["a", "b", "c", "d"]);
+        assert_eq!(start_order(&graph(&reversed)).unwrap(), order);
+    }
+
+    #[test]
+    fn start_order_reports_a_cycle() {
+        let graph = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+
+        assert_eq!(start_order(&graph).unwrap_err(), ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn resolve_picks_the_highest_matching_version() {
+        let modules =
+            HashMap::from([("p".to_string(), versions("p", &["1.0.0", "1.2.0", "2.0.0"]))]);
+
+        let resolved = resolve(&Dependency::new("p", "^1"), &modules).ok();
+        assert_eq!(resolved, Some(vec!["p@1.2.0".to_string()]));
+    }
+
+    #[test]
+    fn resolve_reports_a_missing_dependency() {
+        let modules = HashMap::from([("p".to_string(), versions("p", &["1.0.0"]))]);
+
+        let unmet = resolve(&Dependency::new("q", "^1"), &modules).unwrap_err();
+        assert!(unmet.missing);
+
+        let unmet = resolve(&Dependency::new("p", "^2"), &modules).unwrap_err();
+        assert!(!unmet.missing);
+        assert!(
+            unmet.reason.contains("registered: 1.0.0"),
+            "{}",
+            unmet.reason
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_an_invalid_requirement() {
+        let modules = HashMap::from([("p".to_string(), versions("p", &["1.0.0"]))]);
+
+        let unmet = resolve(&Dependency::new("p", "not a version"), &modules).unwrap_err();
+        assert!(!unmet.missing);
+    }
+}
@@ -0,0 +1,117 @@
+use modular_core::{Callback, CallbackError, CallbackSuccess, StreamCallback};
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What an invocation is counted against, by module id: the module it is made to, and the
+/// module that made it through its scoped registry, since the callback runs its code.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub(crate) enum Flight {
+    To(String),
+    From(String),
+}
+
+/// Invocations whose callback hasn't completed yet, per module, since either end may run
+/// the code of the module once it is gone.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    counts: Mutex<HashMap<Flight, usize>>,
+    drained: Condvar,
+}
+
+impl InFlight {
+    /// Counts an invocation against each of `keys` until the returned `Pending` completes.
+    pub fn track(self: &Arc<Self>, keys: Vec<Flight>) -> Pending {
+        let mut counts = self.counts.lock();
+        for key in &keys {
+            *counts.entry(key.clone()).or_default() += 1;
+        }
+
+        Pending {
+            in_flight: self.clone(),
+            keys,
+        }
+    }
+
+    /// Blocks until nothing is in flight for `key` or `deadline` passes, returning the
+    /// number of invocations still in flight.
+    pub fn wait(&self, key: &Flight, deadline: Instant) -> usize {
+        let mut counts = self.counts.lock();
+
+        loop {
+            let count = counts.get(key).copied().unwrap_or_default();
+            if count == 0 || self.drained.wait_until(&mut counts, deadline).timed_out() {
+                return counts.get(key).copied().unwrap_or_default();
+            }
+        }
+    }
+
+    fn complete(&self, keys: &[Flight]) {
+        let mut counts = self.counts.lock();
+
+        for key in keys {
+            if let Some(count) = counts.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(key);
+                }
+            }
+        }
+
+        self.drained.notify_all();
+    }
+}
+
+/// An invocation counted by `InFlight` until dropped.
+pub(crate) struct Pending {
+    in_flight: Arc<InFlight>,
+    keys: Vec<Flight>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.in_flight.complete(&self.keys)
+    }
+}
+
+/// Wraps the callback of an invocation so it stays counted until dropped, rather than
+/// completed: dropping a callback may run code of the module that created it.
+pub(crate) struct Tracked<C: ?Sized> {
+    // dropped before `_pending`
+    callback: Box<C>,
+    _pending: Pending,
+}
+
+impl<C: ?Sized> Tracked<C> {
+    pub fn new(pending: Pending, callback: Box<C>) -> Self {
+        Self {
+            callback,
+            _pending: pending,
+        }
+    }
+}
+
+impl Callback for Tracked<dyn Callback> {
+    fn on_success(&self, result: CallbackSuccess) {
+        self.callback.on_success(result)
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.callback.on_error(err)
+    }
+}
+
+impl StreamCallback for Tracked<dyn StreamCallback> {
+    fn on_next(&self, result: CallbackSuccess) {
+        self.callback.on_next(result)
+    }
+
+    fn on_complete(&self) {
+        self.callback.on_complete()
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.callback.on_error(err)
+    }
+}
@@ -0,0 +1,44 @@
+use crate::address::split_id;
+use modular_core::{catch_panic, RegistryEvent, RegistryEventKind, RegistryWatcher};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::error;
+
+/// The watchers of a registry. Events are delivered outside of any registry lock, so
+/// watchers may call back into the registry.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    next_id: AtomicU64,
+    watchers: Mutex<Vec<(u64, Arc<dyn RegistryWatcher>)>>,
+}
+
+impl Watchers {
+    pub fn add(&self, watcher: Box<dyn RegistryWatcher>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.watchers.lock().push((id, Arc::from(watcher)));
+        id
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.watchers.lock().retain(|(i, _)| *i != id);
+    }
+
+    /// Notifies every watcher of `kind` happening to the module `id`.
+    pub fn emit(&self, kind: RegistryEventKind, id: &str) {
+        let (package, version) = split_id(id);
+        let event = RegistryEvent {
+            kind,
+            package: package.to_string(),
+            version: version.to_string(),
+        };
+
+        let watchers = self.watchers.lock().clone();
+
+        for (_, watcher) in watchers {
+            if let Err(e) = catch_panic(|| watcher.on_event(&event)) {
+                error!("panic in registry watcher: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use modular_core::{Callback, CancellationToken, InvocationContext, InvokeOptions, StreamCallback};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// An invocation as interceptors see it. Each can rewrite it before passing it on.
+#[derive(Debug, Clone)]
+pub struct Call<'a> {
+    pub package: Cow<'a, str>,
+    pub method: Cow<'a, str>,
+    pub data: Option<Cow<'a, [u8]>>,
+    /// The headers and parent of the options have been moved into `context`.
+    pub options: InvokeOptions,
+    pub context: InvocationContext,
+}
+
+/// Runs around every invocation of `Modular`, including those arriving over a
+/// `NativeRegistry`, in the order the interceptors were added.
+///
+/// An interceptor can pass the call on to `next`, possibly rewritten or with a callback
+/// wrapping the one it was given, or complete the callback itself and return a token of
+/// its own to short-circuit the call.
+pub trait Interceptor: Send + Sync {
+    fn invoke(
+        &self,
+        call: Call,
+        callback: Box<dyn Callback>,
+        next: Next<dyn Callback>,
+    ) -> CancellationToken {
+        next.run(call, callback)
+    }
+
+    fn invoke_stream(
+        &self,
+        call: Call,
+        callback: Box<dyn StreamCallback>,
+        next: Next<dyn StreamCallback>,
+    ) -> CancellationToken {
+        next.run(call, callback)
+    }
+}
+
+type Dispatch<'a, C> = &'a dyn Fn(Call<'_>, Box<C>) -> CancellationToken;
+
+/// The rest of the chain, ending with the invoked module.
+pub struct Next<'a, C: ?Sized> {
+    interceptors: &'a [Arc<dyn Interceptor>],
+    dispatch: Dispatch<'a, C>,
+}
+
+impl<'a, C: ?Sized> Next<'a, C> {
+    pub(crate) fn new(interceptors: &'a [Arc<dyn Interceptor>], dispatch: Dispatch<'a, C>) -> Self {
+        Self {
+            interceptors,
+            dispatch,
+        }
+    }
+}
+
+impl Next<'_, dyn Callback> {
+    pub fn run(self, call: Call, callback: Box<dyn Callback>) -> CancellationToken {
+        match self.interceptors.split_first() {
+            Some((interceptor, rest)) => {
+                interceptor.invoke(call, callback, Next::new(rest, self.dispatch))
+            }
+            None => (self.dispatch)(call, callback),
+        }
+    }
+}
+
+impl Next<'_, dyn StreamCallback> {
+    pub fn run(self, call: Call, callback: Box<dyn StreamCallback>) -> CancellationToken {
+        match self.interceptors.split_first() {
+            Some((interceptor, rest)) => {
+                interceptor.invoke_stream(call, callback, Next::new(rest, self.dispatch))
+            }
+            None => (self.dispatch)(call, callback),
+        }
+    }
+}
@@ -1,4 +1,28 @@
+mod address;
+#[cfg(feature = "config")]
+mod config;
+mod dependencies;
+mod drain;
+mod events;
+mod interceptor;
+mod lifecycle;
+#[cfg(feature = "loader")]
+mod loader;
+mod metrics;
 mod modular;
+mod permissions;
+mod scoped;
+mod swap;
+mod timer;
 
+#[cfg(feature = "config")]
+pub use config::*;
+pub use interceptor::*;
+pub use lifecycle::{ModuleState, ShutdownReport};
+#[cfg(feature = "loader")]
+pub use loader::*;
+pub use metrics::{Histogram, MethodMetrics, MetricsSnapshot, Outcome, UNKNOWN};
 pub use modular::*;
 pub use modular_core::*;
+pub use permissions::*;
+pub use scoped::*;
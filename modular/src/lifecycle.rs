@@ -0,0 +1,161 @@
+use crate::events::Watchers;
+use modular_core::{catch_panic, Module, RegistryEventKind};
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::error;
+
+/// Where a registered module is in its lifecycle.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ModuleState {
+    Registered,
+    Initialized,
+    Running,
+    Stopping,
+    Stopped,
+    ShutDown,
+    Failed,
+}
+
+/// Outcome of `Modular::shutdown`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ShutdownReport {
+    /// Modules that stopped and shut down.
+    pub stopped: Vec<String>,
+    /// Modules that failed in `stop` or `shutdown`, or didn't stop within the grace period.
+    pub failed: Vec<String>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// States of the registered modules, with a condvar notified on every change. Watchers
+/// get an event when a module is registered, starts running, fails or is removed.
+#[derive(Default)]
+pub(crate) struct Lifecycle {
+    states: Mutex<HashMap<String, ModuleState>>,
+    changed: Condvar,
+    pub watchers: Watchers,
+}
+
+impl Lifecycle {
+    pub fn get(&self, package: &str) -> Option<ModuleState> {
+        self.states.lock().get(package).copied()
+    }
+
+    pub fn set(&self, package: &str, state: ModuleState) {
+        self.set_quietly(package, state);
+        self.emit(package, state);
+    }
+
+    pub fn remove(&self, package: &str) {
+        self.remove_quietly(package);
+        self.emit_removed(package);
+    }
+
+    /// `set` without the event, for changes made while holding a lock watchers could need;
+    /// the caller emits it with `emit` once the lock is released.
+    pub fn set_quietly(&self, package: &str, state: ModuleState) {
+        self.states.lock().insert(package.to_string(), state);
+        self.changed.notify_all();
+    }
+
+    /// `remove` without the event, to be emitted with `emit_removed`.
+    pub fn remove_quietly(&self, package: &str) {
+        self.states.lock().remove(package);
+        self.changed.notify_all();
+    }
+
+    pub fn emit_removed(&self, package: &str) {
+        self.watchers
+            .emit(RegistryEventKind::ModuleDeregistered, package);
+    }
+
+    pub fn emit(&self, package: &str, state: ModuleState) {
+        let kind = match state {
+            ModuleState::Registered => RegistryEventKind::ModuleRegistered,
+            ModuleState::Running => RegistryEventKind::ModuleStarted,
+            ModuleState::Failed => RegistryEventKind::ModuleFailed,
+            _ => return,
+        };
+
+        self.watchers.emit(kind, package);
+    }
+
+    /// Moves `package` to `to` if it is in one of the `from` states.
+    pub fn transition(&self, package: &str, from: &[ModuleState], to: ModuleState) -> bool {
+        let mut states = self.states.lock();
+
+        match states.get_mut(package) {
+            Some(state) if from.contains(state) => {
+                *state = to;
+                drop(states);
+                self.changed.notify_all();
+                self.emit(package, to);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Blocks until none of `packages` is in one of `states`, or `deadline` passes.
+    /// Returns whether that happened before the deadline.
+    pub fn wait_while_in(
+        &self,
+        packages: &[String],
+        states: &[ModuleState],
+        deadline: Option<Instant>,
+    ) -> bool {
+        let mut lock = self.states.lock();
+
+        loop {
+            let pending = packages
+                .iter()
+                .any(|p| lock.get(p).is_some_and(|s| states.contains(s)));
+            if !pending {
+                return true;
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    if self.changed.wait_until(&mut lock, deadline).timed_out() {
+                        return false;
+                    }
+                }
+                None => self.changed.wait(&mut lock),
+            }
+        }
+    }
+
+    /// Blocks until no module is in one of `states`, including modules added meanwhile.
+    pub fn wait_while_any_in(&self, states: &[ModuleState]) {
+        let mut lock = self.states.lock();
+
+        while lock.values().any(|s| states.contains(s)) {
+            self.changed.wait(&mut lock);
+        }
+    }
+}
+
+/// Calls a lifecycle hook, returning whether it neither panicked nor marked the module failed.
+pub(crate) fn call_hook(
+    package: &str,
+    hook: &str,
+    module: &dyn Module,
+    f: fn(&dyn Module),
+) -> bool {
+    match catch_panic(|| f(module)) {
+        Ok(()) if !module.is_failed() => true,
+        Ok(()) => {
+            error!("module {:?} failed in {}", package, hook);
+            false
+        }
+        Err(e) => {
+            error!("panic in module {:?} {}: {}", package, hook, e);
+            false
+        }
+    }
+}
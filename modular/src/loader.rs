@@ -0,0 +1,267 @@
+use crate::Modular;
+use modular_core::{Error, Module, ModuleKind, Registry};
+use modular_dll::{DllModule, DllModuleError};
+use native_recorder::BytesRecorder;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Deserializer};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+/// Name of the manifest `Modular::load_directory` looks for in each subdirectory.
+pub const MANIFEST_NAME: &str = "module.toml";
+
+/// Describes a module to load, e.g.
+///
+/// ```toml
+/// kind = "dll"
+/// path = "libmodule1.so"
+/// package = "dll.module1"
+/// version = "0.0.1"
+///
+/// [config]
+/// greeting = "hello"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// `dll` or `wasm`.
+    #[serde(deserialize_with = "deserialize_kind")]
+    pub kind: ModuleKind,
+    /// The binary, relative to the manifest.
+    pub path: PathBuf,
+    /// Package the module has to report.
+    pub package: String,
+    /// Requirement the version the module reports has to match, as for dependencies.
+    pub version: String,
+    /// Handed to the module's `configure` hook as JSON.
+    pub config: Option<toml::Table>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, resolving the binary relative to it.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let manifest: Self = toml::from_str(&fs::read_to_string(path)?)?;
+
+        Ok(manifest.relative_to(path))
+    }
+
+    /// Resolves the binary relative to the file at `path` the manifest comes from.
+    pub(crate) fn relative_to(mut self, path: &Path) -> Self {
+        if let Some(dir) = path.parent() {
+            self.path = dir.join(&self.path);
+        }
+        self
+    }
+}
+
+fn deserialize_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ModuleKind, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "dll" => Ok(ModuleKind::Dll),
+        "wasm" => Ok(ModuleKind::Wasm),
+        kind => Err(serde::de::Error::unknown_variant(kind, &["dll", "wasm"])),
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Manifest(toml::de::Error),
+    /// The host config of `Modular::from_config` is invalid.
+    Config(toml::de::Error),
+    /// The manifest's version isn't a valid requirement.
+    InvalidVersion(semver::Error),
+    Dll(DllModuleError),
+    Wasm(String),
+    /// The module reports another package or version than its manifest.
+    Mismatch {
+        expected: String,
+        found: String,
+    },
+    Configure(String),
+    Register(Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read: {}", e),
+            Self::Manifest(e) => write!(f, "invalid manifest: {}", e),
+            Self::Config(e) => write!(f, "invalid host config: {}", e),
+            Self::InvalidVersion(e) => write!(f, "invalid version requirement: {}", e),
+            Self::Dll(e) => write!(f, "{}", e),
+            Self::Wasm(e) => write!(f, "failed to load wasm module: {}", e),
+            Self::Mismatch { expected, found } => {
+                write!(f, "expected module {}, found {}", expected, found)
+            }
+            Self::Configure(e) => write!(f, "failed to configure module: {}", e),
+            Self::Register(e) => write!(f, "failed to register module: {}", e.as_ref()),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Manifest(e) | Self::Config(e) => Some(e),
+            Self::InvalidVersion(e) => Some(e),
+            Self::Dll(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for LoadError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Manifest(e)
+    }
+}
+
+/// Outcome of `Modular::load_directory`.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Packages of the modules registered.
+    pub loaded: Vec<String>,
+    /// Manifests, or directories, that failed to load; for the modules listed in the host
+    /// config of `Modular::from_config`, their binaries.
+    pub failed: Vec<(PathBuf, LoadError)>,
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl Modular {
+    /// Registers the module of every subdirectory of `dir` with a `module.toml` manifest,
+    /// in the order of their names, each scoped to its package. One failing doesn't stop
+    /// the others from loading; failures are logged and reported.
+    pub fn load_directory<P: AsRef<Path>, L: BytesRecorder + 'static>(
+        &self,
+        dir: P,
+        recorder: L,
+    ) -> LoadReport {
+        let dir = dir.as_ref();
+        let mut report = LoadReport::default();
+
+        let mut manifests: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path().join(MANIFEST_NAME)))
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(e) => {
+                error!("failed to read module directory {}: {}", dir.display(), e);
+                report.failed.push((dir.to_path_buf(), e.into()));
+                return report;
+            }
+        };
+        manifests.sort();
+
+        for path in manifests {
+            match self.load_manifest(&path, recorder.clone()) {
+                Ok(package) => report.loaded.push(package),
+                Err(e) => {
+                    error!("failed to load module from {}: {}", path.display(), e);
+                    report.failed.push((path, e));
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Loads and registers the module the manifest at `path` describes, returning its
+    /// package.
+    pub fn load_manifest<P: AsRef<Path>, L: BytesRecorder + 'static>(
+        &self,
+        path: P,
+        recorder: L,
+    ) -> Result<String, LoadError> {
+        self.register_manifest(Manifest::read(path)?, recorder)
+    }
+
+    /// Loads and registers the module `manifest` describes, returning its package.
+    pub fn register_manifest<L: BytesRecorder + 'static>(
+        &self,
+        manifest: Manifest,
+        recorder: L,
+    ) -> Result<String, LoadError> {
+        let module = self.load_module(&manifest, recorder)?;
+
+        self.register_module(module).map_err(LoadError::Register)?;
+        info!(
+            "loaded module {:?} from {}",
+            manifest.package,
+            manifest.path.display()
+        );
+
+        Ok(manifest.package)
+    }
+
+    /// Loads and configures the module `manifest` describes, without registering it.
+    pub fn load_module<L: BytesRecorder + 'static>(
+        &self,
+        manifest: &Manifest,
+        recorder: L,
+    ) -> Result<Box<dyn Module>, LoadError> {
+        let requirement =
+            VersionReq::parse(&manifest.version).map_err(LoadError::InvalidVersion)?;
+        let registry = self.scoped(&manifest.package);
+
+        let module: Box<dyn Module> = match manifest.kind {
+            ModuleKind::Wasm => load_wasm(&manifest.path, registry)?,
+            _ => Box::new(
+                DllModule::new(&manifest.path, &registry, recorder).map_err(LoadError::Dll)?,
+            ),
+        };
+
+        let matches = module.package() == manifest.package
+            && Version::parse(module.version()).is_ok_and(|v| requirement.matches(&v));
+        if !matches {
+            return Err(LoadError::Mismatch {
+                expected: format!("{}@{}", manifest.package, manifest.version),
+                found: format!("{}@{}", module.package(), module.version()),
+            });
+        }
+
+        if let Some(config) = &manifest.config {
+            let config =
+                serde_json::to_vec(config).map_err(|e| LoadError::Configure(e.to_string()))?;
+            module.configure(&config).map_err(LoadError::Configure)?;
+        }
+
+        Ok(module)
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm<R: Registry + 'static>(
+    path: &Path,
+    registry: R,
+) -> Result<Box<dyn Module>, LoadError> {
+    let bytes = fs::read(path)?;
+    let module = modular_wasm::WasmModule::new(bytes, registry)
+        .map_err(|e| LoadError::Wasm(e.to_string()))?;
+
+    Ok(Box::new(module))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm<R: Registry + 'static>(
+    _path: &Path,
+    _registry: R,
+) -> Result<Box<dyn Module>, LoadError> {
+    Err(LoadError::Wasm(
+        "wasm modules need the `wasm` feature of modular".to_string(),
+    ))
+}
@@ -0,0 +1,337 @@
+use modular_core::{Callback, CallbackError, CallbackSuccess, Error, StreamCallback};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How an invocation completed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Outcome {
+    Success,
+    Error,
+    Timeout,
+    Cancelled,
+}
+
+impl Outcome {
+    fn from_code(code: i32) -> Self {
+        if code == Error::Timeout as i32 {
+            Outcome::Timeout
+        } else if code == Error::Cancelled as i32 {
+            Outcome::Cancelled
+        } else {
+            Outcome::Error
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Error => "error",
+            Outcome::Timeout => "timeout",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Latencies of the invocations of one method with one outcome.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: Duration,
+    /// Upper bound of each bucket in seconds, with the number of invocations that took
+    /// at most as long. Cumulative, as in Prometheus.
+    pub buckets: Vec<(f64, u64)>,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        if self.buckets.is_empty() {
+            self.buckets = BUCKETS.iter().map(|b| (*b, 0)).collect();
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        for (bound, count) in &mut self.buckets {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += elapsed;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodMetrics {
+    pub package: String,
+    pub method: String,
+    /// Invocations dispatched whose callback hasn't completed yet.
+    pub in_flight: u64,
+    pub outcomes: BTreeMap<Outcome, Histogram>,
+}
+
+impl MethodMetrics {
+    pub fn total(&self) -> u64 {
+        self.outcomes.values().map(|h| h.count).sum()
+    }
+}
+
+/// Invocation metrics of a `Modular` at one point in time, per package and method.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub methods: Vec<MethodMetrics>,
+}
+
+impl MetricsSnapshot {
+    pub fn get(&self, package: &str, method: &str) -> Option<&MethodMetrics> {
+        self.methods
+            .iter()
+            .find(|m| m.package == package && m.method == method)
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP modular_invocations_total Completed invocations.\n");
+        out.push_str("# TYPE modular_invocations_total counter\n");
+        for m in &self.methods {
+            for (outcome, histogram) in &m.outcomes {
+                let _ = writeln!(
+                    out,
+                    "modular_invocations_total{{{}}} {}",
+                    labels(m, Some(*outcome)),
+                    histogram.count
+                );
+            }
+        }
+
+        out.push_str("# HELP modular_invocation_duration_seconds Time from invoke until the callback completed.\n");
+        out.push_str("# TYPE modular_invocation_duration_seconds histogram\n");
+        for m in &self.methods {
+            for (outcome, histogram) in &m.outcomes {
+                let labels = labels(m, Some(*outcome));
+
+                for (bound, count) in &histogram.buckets {
+                    let _ = writeln!(
+                        out,
+                        "modular_invocation_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                        labels, bound, count
+                    );
+                }
+
+                let _ = writeln!(
+                    out,
+                    "modular_invocation_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                    labels, histogram.count
+                );
+                let _ = writeln!(
+                    out,
+                    "modular_invocation_duration_seconds_sum{{{}}} {}",
+                    labels,
+                    histogram.sum.as_secs_f64()
+                );
+                let _ = writeln!(
+                    out,
+                    "modular_invocation_duration_seconds_count{{{}}} {}",
+                    labels, histogram.count
+                );
+            }
+        }
+
+        out.push_str("# HELP modular_invocations_in_flight Invocations not completed yet.\n");
+        out.push_str("# TYPE modular_invocations_in_flight gauge\n");
+        for m in &self.methods {
+            let _ = writeln!(
+                out,
+                "modular_invocations_in_flight{{{}}} {}",
+                labels(m, None),
+                m.in_flight
+            );
+        }
+
+        out
+    }
+}
+
+fn labels(metrics: &MethodMetrics, outcome: Option<Outcome>) -> String {
+    let mut labels = format!(
+        "package=\"{}\",method=\"{}\"",
+        escape(&metrics.package),
+        escape(&metrics.method)
+    );
+
+    if let Some(outcome) = outcome {
+        let _ = write!(labels, ",outcome=\"{}\"", outcome.as_str());
+    }
+
+    labels
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The package and method the invocations of a package or method that doesn't exist are
+/// recorded under, rather than a series each, since callers can make up any number of them.
+pub const UNKNOWN: &str = "<unknown>";
+
+/// Metrics of every package and method invoked so far.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    methods: Mutex<BTreeMap<(String, String), MethodMetrics>>,
+}
+
+impl Metrics {
+    fn started(&self, package: &str, method: &str) {
+        entry(&mut self.methods.lock(), package, method).in_flight += 1;
+    }
+
+    /// Records an invocation started with `package` and `method` as completed, under
+    /// `UNKNOWN` if they turned out not to exist.
+    fn completed(
+        &self,
+        package: &str,
+        method: &str,
+        outcome: Outcome,
+        resolved: bool,
+        elapsed: Duration,
+    ) {
+        let mut methods = self.methods.lock();
+        let key = (package.to_string(), method.to_string());
+
+        let Some(metrics) = methods.get_mut(&key) else {
+            return;
+        };
+        metrics.in_flight = metrics.in_flight.saturating_sub(1);
+
+        let metrics = if resolved {
+            metrics
+        } else {
+            if metrics.in_flight == 0 && metrics.outcomes.is_empty() {
+                methods.remove(&key);
+            }
+            entry(&mut methods, UNKNOWN, UNKNOWN)
+        };
+
+        metrics
+            .outcomes
+            .entry(outcome)
+            .or_default()
+            .observe(elapsed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            methods: self.methods.lock().values().cloned().collect(),
+        }
+    }
+}
+
+fn entry<'a>(
+    methods: &'a mut BTreeMap<(String, String), MethodMetrics>,
+    package: &str,
+    method: &str,
+) -> &'a mut MethodMetrics {
+    methods
+        .entry((package.to_string(), method.to_string()))
+        .or_insert_with(|| MethodMetrics {
+            package: package.to_string(),
+            method: method.to_string(),
+            ..Default::default()
+        })
+}
+
+/// Whether an invocation failing with `code` reached an existing method.
+fn is_resolved(code: i32) -> bool {
+    code != Error::ModuleNotFound as i32 && code != Error::MethodNotFound as i32
+}
+
+/// Wraps the callback of an invocation to record it once it completes. One dropped
+/// without completing counts as an error.
+pub(crate) struct Measured<C: ?Sized> {
+    metrics: Arc<Metrics>,
+    package: String,
+    method: String,
+    start: Instant,
+    done: AtomicBool,
+    callback: Box<C>,
+}
+
+impl<C: ?Sized> Measured<C> {
+    pub fn new(metrics: Arc<Metrics>, package: &str, method: &str, callback: Box<C>) -> Self {
+        // the requirement of an address doesn't make a series of its own
+        let package = package.split_once('@').map_or(package, |(p, _)| p);
+        metrics.started(package, method);
+
+        Self {
+            metrics,
+            package: package.to_string(),
+            method: method.to_string(),
+            start: Instant::now(),
+            done: AtomicBool::new(false),
+            callback,
+        }
+    }
+
+    fn finish(&self, outcome: Outcome, resolved: bool) {
+        if !self.done.swap(true, Ordering::AcqRel) {
+            self.metrics.completed(
+                &self.package,
+                &self.method,
+                outcome,
+                resolved,
+                self.start.elapsed(),
+            );
+        }
+    }
+
+    fn fail(&self, err: &CallbackError) {
+        self.finish(Outcome::from_code(err.code), is_resolved(err.code));
+    }
+}
+
+impl<C: ?Sized> Drop for Measured<C> {
+    fn drop(&mut self) {
+        self.finish(Outcome::Error, true);
+    }
+}
+
+impl Callback for Measured<dyn Callback> {
+    fn on_success(&self, result: CallbackSuccess) {
+        self.finish(Outcome::Success, true);
+        self.callback.on_success(result)
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.fail(&err);
+        self.callback.on_error(err)
+    }
+}
+
+impl StreamCallback for Measured<dyn StreamCallback> {
+    fn on_next(&self, result: CallbackSuccess) {
+        self.callback.on_next(result)
+    }
+
+    fn on_complete(&self) {
+        self.finish(Outcome::Success, true);
+        self.callback.on_complete()
+    }
+
+    fn on_error(&self, err: CallbackError) {
+        self.fail(&err);
+        self.callback.on_error(err)
+    }
+}
@@ -1,17 +1,83 @@
+use crate::address::{module_id, split_id, Address, Replicas, Versions};
+use crate::dependencies::{resolve, start_order, DependencyGraph, Unmet};
+use crate::drain::{Flight, InFlight, Pending, Tracked};
+use crate::interceptor::{Call, Interceptor, Next};
+use crate::lifecycle::{call_hook, Lifecycle, ModuleState, ShutdownReport};
+use crate::metrics::{Measured, Metrics, MetricsSnapshot};
+use crate::permissions::{glob_match, Permissions};
+use crate::scoped::{Bindings, ScopedRegistry};
+use crate::swap::Swaps;
+use crate::timer::Timer;
 use modular_core::Error;
-use modular_core::{Callback, CallbackError, Module, NativeRegistry, Registry};
+use modular_core::{
+    catch_panic, current_invocation, with_current_deadline, with_current_invocation, Callback,
+    CallbackError, CallbackGuard, Caller, CancellationToken, InvocationContext, InvokeOptions,
+    MethodDescriptor, Module, ModuleQuery, NativeRegistry, RegisteredModule, Registry,
+    RegistryWatcher, StreamCallback, StreamCallbackGuard, Watch,
+};
 use parking_lot::{Mutex, RwLock};
+use semver::Version;
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info};
 
-type ModularEntity = Arc<RwLock<Box<dyn Module>>>;
+pub(crate) type ModularEntity = Arc<RwLock<Box<dyn Module>>>;
+
+/// What `register_module` does with a module whose package and version are registered already.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DuplicatePolicy {
+    /// Fail with `Error::ModuleAlreadyRegistered`.
+    #[default]
+    Reject,
+    /// Deregister the modules registered so far.
+    Replace,
+    /// Keep them all, invocations going to each in turn.
+    Replicate,
+}
+
+/// What `swap_module` does when the module swapped in can't be handed the state of the one
+/// it replaces, because it fails to restore it or the old one doesn't drain in time.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RestorePolicy {
+    /// Swap it in all the same, without the state.
+    #[default]
+    KeepNew,
+    /// Keep the old module, failing with `Error::RestoreFailed` or `Error::Timeout`.
+    KeepOld,
+}
 
 #[derive(Clone)]
 pub struct Modular {
-    modules: Arc<RwLock<HashMap<String, ModularEntity>>>,
+    modules: Arc<RwLock<HashMap<String, Versions>>>,
+    duplicate_policy: DuplicatePolicy,
+    interceptors: Arc<Vec<Arc<dyn Interceptor>>>,
+    permissions: Arc<Permissions>,
     is_running: Arc<Mutex<bool>>,
+    timer: Arc<Timer>,
+    lifecycle: Arc<Lifecycle>,
+    start_order: Arc<Mutex<Vec<String>>>,
+    next_invocation: Arc<AtomicU64>,
+    metrics: Arc<Metrics>,
+    in_flight: Arc<InFlight>,
+    drain_timeout: Duration,
+    restore_policy: RestorePolicy,
+    swaps: Arc<Swaps>,
+    bindings: Arc<Bindings>,
 }
 
 impl Default for Modular {
@@ -22,95 +88,1030 @@ impl Default for Modular {
 
         Self {
             modules: Arc::new(RwLock::new(HashMap::new())),
+            duplicate_policy: DuplicatePolicy::default(),
+            interceptors: Arc::new(vec![]),
+            permissions: Arc::new(Permissions::default()),
             is_running: Arc::new(Mutex::new(false)),
+            timer: Arc::new(Timer::new()),
+            lifecycle: Arc::new(Lifecycle::default()),
+            start_order: Arc::new(Mutex::new(vec![])),
+            next_invocation: Arc::new(AtomicU64::new(1)),
+            metrics: Arc::new(Metrics::default()),
+            in_flight: Arc::new(InFlight::default()),
+            drain_timeout: Duration::from_secs(10),
+            restore_policy: RestorePolicy::default(),
+            swaps: Arc::new(Swaps::default()),
+            bindings: Arc::new(Bindings::default()),
         }
     }
 }
 
-impl Modular {}
+impl Modular {
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
 
-impl Registry for Modular {
-    fn run(&self) -> Result<(), Error> {
-        let mut lock = self.is_running.lock();
-        if *lock {
-            return Err(Error::RegistryAlreadyRunning);
+    pub fn with_restore_policy(mut self, policy: RestorePolicy) -> Self {
+        self.restore_policy = policy;
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = Arc::new(permissions);
+        self
+    }
+
+    /// How long deregistering a module waits for the invocations made to it, and those it
+    /// made, to finish before giving up on unloading it. 10 seconds by default.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub(crate) fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    /// Counts, latencies and in-flight invocations per package, method and outcome, measured
+    /// from invoke until the callback completes. Those of packages or methods not found are
+    /// counted under `UNKNOWN`.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Registry handle for the module `caller`, to be passed to it when it is created.
+    /// Calls made through `Modular` itself are the host's, and aren't checked.
+    pub fn scoped(&self, caller: &str) -> ScopedRegistry {
+        let binding = self.bindings.new_binding(caller);
+        ScopedRegistry::new(self.clone(), caller, binding)
+    }
+
+    /// Adds an interceptor to the end of the chain every invocation goes through.
+    pub fn with_interceptor<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        Arc::make_mut(&mut self.interceptors).push(Arc::new(interceptor));
+        self
+    }
+
+    /// Creates the token of an invocation. Cancelling it, or reaching `deadline`, fails
+    /// the callback unless the callee completed it already.
+    fn invocation_token<C: PendingCallback + 'static>(
+        &self,
+        callback: &Arc<C>,
+        deadline: Option<Instant>,
+    ) -> CancellationToken {
+        let token = CancellationToken::with_deadline(deadline);
+
+        let weak = Arc::downgrade(callback);
+        token.on_cancel(move || {
+            if let Some(callback) = weak.upgrade().filter(|c| !c.is_completed()) {
+                callback.fail(cancelled_error());
+            }
+        });
+
+        if let Some(deadline) = deadline {
+            let weak = Arc::downgrade(callback);
+            let timed_out = token.clone();
+
+            self.timer.schedule(deadline, move || {
+                if let Some(callback) = weak.upgrade().filter(|c| !c.is_completed()) {
+                    callback.fail(timeout_error());
+                    timed_out.cancel();
+                }
+            });
         }
-        *lock = true;
-        drop(lock);
 
-        let modules = self
-            .modules
+        token
+    }
+
+    /// Every registered module, with its id.
+    fn entities(&self) -> Vec<(String, ModularEntity)> {
+        self.modules
             .read()
-            .iter()
-            .map(|(_, module)| module.clone())
-            .collect::<Vec<_>>();
+            .values()
+            .flat_map(|versions| versions.values())
+            .flat_map(|replicas| replicas.iter().cloned())
+            .collect()
+    }
 
-        let mut join_handles = vec![];
+    /// The module an address points to, with its id, or why there is none.
+    fn resolve(&self, address: &str) -> Result<(String, ModularEntity), String> {
+        resolve_in(&self.modules.read(), address)
+    }
 
-        for module in modules {
-            let jh = thread::spawn(move || {
-                module.read().run();
+    /// `resolve` for an invocation, counted in flight for the module until the returned
+    /// `Pending` is dropped. Counted under the same lock deregistration takes, so that a
+    /// drain can't miss it. Waits until `deadline` while the package is being swapped.
+    fn resolve_invocation(
+        &self,
+        address: &str,
+        deadline: Option<Instant>,
+    ) -> Result<(String, ModularEntity, Pending), String> {
+        let package = Address::parse(address)?.package;
 
-                debug!("module {:?} run finished", module.read().package());
+        let modules = loop {
+            self.swaps.wait(package, deadline);
 
-                module
-            });
+            // checked again under the lock `swap_module` takes before waiting for the
+            // invocations in flight, so that each is either waited for or waits itself
+            let modules = self.modules.read();
+            if !self.swaps.is_swapping(package) || is_expired(deadline) {
+                break modules;
+            }
+        };
+        let (id, module) = resolve_in(&modules, address)?;
+        let pending = self.in_flight.track(vec![Flight::To(id.clone())]);
 
-            join_handles.push(jh);
-        }
+        Ok((id, module, pending))
+    }
 
-        for jh in join_handles {
-            match jh.join() {
-                Ok(module) => {
-                    debug!("module {:?} thread joined", module.read().package());
-                }
-                Err(_) => {
-                    error!("error joining thread")
+    /// Counts an invocation the module `id` makes in flight until the returned `Pending`
+    /// is dropped.
+    pub(crate) fn track_outgoing(&self, id: String) -> Pending {
+        self.in_flight.track(vec![Flight::From(id)])
+    }
+
+    /// The registered version an address points to, without picking a replica.
+    pub(crate) fn resolve_version(&self, address: &str) -> Option<Version> {
+        let parsed = Address::parse(address).ok()?;
+        let modules = self.modules.read();
+
+        modules
+            .get(parsed.package)
+            .and_then(|versions| parsed.resolve(versions))
+            .map(|(version, _)| version.clone())
+    }
+
+    /// Dependency graph of the registered modules, along with the unmet dependencies of each.
+    fn dependency_graph(&self) -> (DependencyGraph, Vec<(String, Unmet)>) {
+        let modules = self.modules.read().clone();
+
+        let mut graph = HashMap::new();
+        let mut unmet_dependencies = vec![];
+
+        for replicas in modules.values().flat_map(|versions| versions.values()) {
+            for (id, module) in replicas.iter() {
+                let mut dependencies = vec![];
+
+                for dependency in module.read().dependencies() {
+                    match resolve(&dependency, &modules) {
+                        Ok(v) => dependencies.extend(v),
+                        Err(unmet) => unmet_dependencies.push((id.clone(), unmet)),
+                    }
                 }
+
+                graph.insert(id.clone(), dependencies);
             }
         }
 
-        Ok(())
+        (graph, unmet_dependencies)
     }
 
-    fn register_module(&self, module: Box<dyn Module>) {
+    /// Checks the dependencies of a module being registered. Those not registered yet
+    /// may still be before the registry runs, so only the others are reported.
+    fn check_dependencies(&self, id: &str) {
+        let (graph, unmet_dependencies) = self.dependency_graph();
+
+        for (_, unmet) in unmet_dependencies.iter().filter(|(m, _)| m == id) {
+            if !unmet.missing {
+                error!("module {:?} has an unmet dependency: {}", id, unmet.reason);
+            }
+        }
+
+        if let Err(cycle) = start_order(&graph) {
+            error!("dependency cycle: {}", cycle.join(" -> "));
+        }
+    }
+
+    /// The registered modules, each after the modules it depends on, along with the ids
+    /// of those.
+    fn ordered_entities(&self) -> Result<Vec<(String, ModularEntity, Vec<String>)>, Error> {
+        let (mut graph, unmet_dependencies) = self.dependency_graph();
+
+        if !unmet_dependencies.is_empty() {
+            for (id, unmet) in unmet_dependencies {
+                error!("module {:?} has an unmet dependency: {}", id, unmet.reason);
+            }
+            return Err(Error::UnmetDependency);
+        }
+
+        let order = start_order(&graph).map_err(|cycle| {
+            error!("dependency cycle: {}", cycle.join(" -> "));
+            Error::DependencyCycle
+        })?;
+
+        let mut modules = self.entities().into_iter().collect::<HashMap<_, _>>();
+        *self.start_order.lock() = order.clone();
+
+        Ok(order
+            .into_iter()
+            .filter_map(|id| {
+                let module = modules.remove(&id)?;
+                let dependencies = graph.remove(&id).unwrap_or_default();
+                Some((id, module, dependencies))
+            })
+            .collect())
+    }
+
+    /// Runs `init` and `start` of a registered module, returning whether it is now running.
+    fn start_module(&self, package: &str, module: &ModularEntity, dependencies: &[String]) -> bool {
+        let module = module.read();
+
+        let started = [
+            ModuleState::Running,
+            ModuleState::Stopping,
+            ModuleState::Stopped,
+        ];
+        for dependency in dependencies {
+            if !self
+                .lifecycle
+                .get(dependency)
+                .is_some_and(|s| started.contains(&s))
+            {
+                error!(
+                    "module {:?} not started, its dependency {:?} failed to start",
+                    package, dependency
+                );
+                self.lifecycle.set(package, ModuleState::Failed);
+                return false;
+            }
+        }
+
+        if !self.lifecycle.transition(
+            package,
+            &[ModuleState::Registered],
+            ModuleState::Initialized,
+        ) {
+            return false;
+        }
+
+        if !call_hook(package, "init", &**module, |m| m.init()) {
+            self.lifecycle.set(package, ModuleState::Failed);
+            return false;
+        }
+
+        if !call_hook(package, "start", &**module, |m| m.start()) {
+            self.lifecycle.set(package, ModuleState::Failed);
+            return false;
+        }
+
+        self.lifecycle
+            .transition(package, &[ModuleState::Initialized], ModuleState::Running)
+    }
+
+    /// Calls `run` of a started module on a thread of its own.
+    fn spawn_run(&self, package: String, module: ModularEntity) {
+        let lifecycle = self.lifecycle.clone();
+
+        thread::spawn(move || {
+            let ok = call_hook(&package, "run", &**module.read(), |m| m.run());
+
+            debug!("module {:?} run finished", package);
+
+            let state = if ok {
+                ModuleState::Stopped
+            } else {
+                ModuleState::Failed
+            };
+            lifecycle.transition(
+                &package,
+                &[ModuleState::Running, ModuleState::Stopping],
+                state,
+            );
+        });
+    }
+
+    /// Swaps `module` in for every registered version of its package at once, to reload a
+    /// module without restarting the host. Invocations of the package wait while the state
+    /// of the old module is handed over with `Module::snapshot` and `Module::restore` and,
+    /// if the registry is running, `module` is started. The modules it replaces are then
+    /// drained in the background, like deregistered ones, or leaked if invocations of them
+    /// were still running when the drain timeout passed.
+    ///
+    /// Fails with `Error::ModuleNotFound` if the package isn't registered. Under
+    /// `RestorePolicy::KeepOld`, fails with `Error::RestoreFailed` if `module` fails to
+    /// restore the state, and with `Error::Timeout` if invocations of the old module are
+    /// still running when the drain timeout passes, since no state is taken from it then.
+    pub fn swap_module(&self, module: Box<dyn Module>) -> Result<(), Error> {
         let package = module.package().to_string();
+        let version = parse_version(&*module)?;
         let module = Arc::new(RwLock::new(module));
+        let is_running = *self.is_running.lock();
+
+        let swapping = self.swaps.begin(&package);
+
+        // once the lock is taken, every invocation that got past `swaps` is in flight
+        let old: Vec<_> = self
+            .modules
+            .write()
+            .get(&package)
+            .into_iter()
+            .flat_map(|versions| versions.values())
+            .flat_map(|replicas| replicas.iter().cloned())
+            .collect();
+
+        // the most recent replica of the highest version
+        let Some(from) = old.last() else {
+            error!("module {:?} not swapped in, it isn't registered", package);
+            return Err(Error::ModuleNotFound);
+        };
+
+        let deadline = Instant::now() + self.drain_timeout;
+        let remaining: usize = old
+            .iter()
+            .map(|(id, _)| self.in_flight.wait(&Flight::To(id.clone()), deadline))
+            .sum();
+
+        // the state is only consistent once no invocation of the old modules runs anymore
+        let handed_over = match remaining {
+            0 => hand_over(&from.1, &module).map_err(|e| (Error::RestoreFailed, e)),
+            _ => Err((
+                Error::Timeout,
+                format!(
+                    "{} invocations still in flight after {:?}",
+                    remaining, self.drain_timeout
+                ),
+            )),
+        };
+
+        if let Err((e, reason)) = handed_over {
+            error!(
+                "module {:?} not handed the state of {:?}: {}",
+                package, from.0, reason
+            );
+
+            if self.restore_policy == RestorePolicy::KeepOld {
+                info!("keeping module {:?}", from.0);
+                return Err(e);
+            }
+        }
+
+        let mut modules = self.modules.write();
+        let Some(versions) = modules.get_mut(&package) else {
+            error!("module {:?} not swapped in, it was deregistered", package);
+            return Err(Error::ModuleNotFound);
+        };
+
+        let mut replaced = vec![];
+        for replicas in versions.values_mut() {
+            replaced.extend(replicas.take());
+        }
+        versions.retain(|v, _| *v == version);
+
+        let id = module_id(&package, &version);
+        let id = match versions.get_mut(&version) {
+            Some(replicas) => replicas.push(&id, module.clone()),
+            None => {
+                versions.insert(version, Replicas::new(id.clone(), module.clone()));
+                id
+            }
+        };
+
+        let ids: Vec<_> = replaced.iter().map(|(id, _)| id.clone()).collect();
+        self.bindings.bind(&package, &id, &ids);
+        self.lifecycle.set_quietly(&id, ModuleState::Registered);
+        drop(modules);
+
+        info!("module {:?} swapped in for {:?}", id, ids);
+        self.lifecycle.emit(&id, ModuleState::Registered);
+
+        if is_running {
+            self.start_swapped(&id, &module);
+        }
+        drop(swapping);
+
+        // modules still busy are drained against the deadline that passed already, so
+        // leaked unless they finished meanwhile
+        let modular = self.clone();
+        thread::spawn(move || match remaining {
+            0 => modular.drain(replaced),
+            _ => modular.drain_until(replaced, deadline),
+        });
+
+        Ok(())
+    }
+
+    /// Starts a module swapped in while the registry runs.
+    fn start_swapped(&self, id: &str, module: &ModularEntity) {
+        let (mut graph, unmet_dependencies) = self.dependency_graph();
+        let unmet: Vec<_> = unmet_dependencies.iter().filter(|(m, _)| m == id).collect();
+
+        if !unmet.is_empty() {
+            for (_, unmet) in unmet {
+                error!("module {:?} has an unmet dependency: {}", id, unmet.reason);
+            }
+            self.lifecycle.set(id, ModuleState::Failed);
+        } else if self.start_module(id, module, &graph.remove(id).unwrap_or_default()) {
+            self.start_order.lock().push(id.to_string());
+            self.spawn_run(id.to_string(), module.clone());
+        }
+    }
+
+    /// State of the module `address` points to.
+    pub fn module_state(&self, address: &str) -> Option<ModuleState> {
+        let (id, _) = self.resolve(address).ok()?;
+        self.lifecycle.get(&id)
+    }
+
+    /// Stops every running module and waits up to `grace` for their `run` to return,
+    /// then calls `shutdown` on each module that stopped.
+    pub fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + grace;
+
+        // dependents first
+        let order = self.start_order.lock().clone();
+        let mut modules = self.entities();
+        modules.sort_by_key(|(package, _)| Reverse(order.iter().position(|p| p == package)));
+        let mut report = ShutdownReport::default();
+
+        self.stop_modules(&modules, grace, deadline, &mut report);
+        self.shutdown_modules(&modules, &mut report);
+
+        report
+    }
+
+    /// Calls `stop` on those of `modules` that are running, then waits until `deadline`
+    /// for their `run` to return. Modules that don't are marked failed.
+    fn stop_modules(
+        &self,
+        modules: &[(String, ModularEntity)],
+        grace: Duration,
+        deadline: Instant,
+        report: &mut ShutdownReport,
+    ) {
+        let mut stopping = vec![];
+
+        for (package, module) in modules {
+            if !self
+                .lifecycle
+                .transition(package, &[ModuleState::Running], ModuleState::Stopping)
+            {
+                continue;
+            }
+
+            info!("stopping module {:?}", package);
+
+            if call_hook(package, "stop", &**module.read(), |m| m.stop()) {
+                stopping.push(package.clone());
+            } else {
+                self.lifecycle.set(package, ModuleState::Failed);
+                report.failed.push(package.clone());
+            }
+        }
+
+        self.lifecycle
+            .wait_while_in(&stopping, &[ModuleState::Stopping], Some(deadline));
+
+        for package in &stopping {
+            if self
+                .lifecycle
+                .transition(package, &[ModuleState::Stopping], ModuleState::Failed)
+            {
+                error!("module {:?} did not stop within {:?}", package, grace);
+                report.failed.push(package.clone());
+            }
+        }
+    }
+
+    /// `deregister_module`, draining the modules in the background if `background`, as
+    /// needed when a module deregisters one: the module itself, or one it is called by,
+    /// would otherwise wait for the drain to finish, and the drain for it.
+    pub(crate) fn deregister(&self, package: &str, background: bool) -> Result<(), Error> {
+        let removed = Address::parse(package).ok().and_then(|address| {
+            let mut modules = self.modules.write();
+            let versions = modules.get_mut(address.package)?;
+            let (version, _) = address.resolve(versions)?;
+            let version = version.clone();
+
+            let replicas = versions.remove(&version);
+            if versions.is_empty() {
+                modules.remove(address.package);
+            }
+
+            replicas
+        });
+
+        match removed {
+            Some(mut replicas) if background => {
+                let replicas = replicas.take();
+                let modular = self.clone();
+                thread::spawn(move || modular.drain(replicas));
+                Ok(())
+            }
+            Some(mut replicas) => {
+                self.drain(replicas.take());
+                Ok(())
+            }
+            None => {
+                error!("module {:?} not found", package);
+                Err(Error::ModuleNotFound)
+            }
+        }
+    }
+
+    /// Stops `modules`, removed from the registry already, and waits for the invocations
+    /// made to them to finish before calling `shutdown`, then for those they made, whose
+    /// callbacks run their code, before dropping them. Modules still busy when the drain
+    /// timeout passes are leaked instead, since unloading their code would crash the
+    /// threads still in it.
+    fn drain(&self, modules: Vec<(String, ModularEntity)>) {
+        self.drain_until(modules, Instant::now() + self.drain_timeout);
+    }
+
+    /// `drain` with the drain timeout ending at `deadline`.
+    fn drain_until(&self, modules: Vec<(String, ModularEntity)>, deadline: Instant) {
+        let mut report = ShutdownReport::default();
+        self.stop_modules(&modules, self.drain_timeout, deadline, &mut report);
+
+        let remaining = self.wait_in_flight(&modules, Flight::To, deadline);
+        if remaining > 0 {
+            self.leak(modules, remaining, "made to");
+            return;
+        }
+
+        self.shutdown_modules(&modules, &mut report);
+
+        // a module is expected to cancel the calls it still has running on `stop` or
+        // `shutdown`
+        let remaining = self.wait_in_flight(&modules, Flight::From, deadline);
+        if remaining > 0 {
+            self.leak(modules, remaining, "made by");
+            return;
+        }
+
+        for (id, _) in modules {
+            self.lifecycle.remove(&id);
+            info!("module {:?} deregistered", id);
+        }
+    }
+
+    /// Waits until `deadline` for the invocations counted against `modules` as `flight`,
+    /// returning how many are left.
+    fn wait_in_flight(
+        &self,
+        modules: &[(String, ModularEntity)],
+        flight: fn(String) -> Flight,
+        deadline: Instant,
+    ) -> usize {
+        modules
+            .iter()
+            .map(|(id, _)| self.in_flight.wait(&flight(id.clone()), deadline))
+            .sum()
+    }
+
+    fn leak(&self, modules: Vec<(String, ModularEntity)>, remaining: usize, direction: &str) {
+        let ids: Vec<_> = modules.iter().map(|(id, _)| id.clone()).collect();
+        error!(
+            "{} invocations {} {:?} still in flight after {:?}, leaking the modules",
+            remaining, direction, ids, self.drain_timeout
+        );
+
+        for id in ids {
+            self.lifecycle.remove(&id);
+        }
+        std::mem::forget(modules);
+    }
 
-        info!("registering module {:?}", package);
+    /// Calls `shutdown` on those of `modules` that were initialized and aren't running.
+    fn shutdown_modules(&self, modules: &[(String, ModularEntity)], report: &mut ShutdownReport) {
+        for (package, module) in modules {
+            let from = [ModuleState::Initialized, ModuleState::Stopped];
+            if !self
+                .lifecycle
+                .get(package)
+                .is_some_and(|s| from.contains(&s))
+            {
+                continue;
+            }
 
-        self.modules.write().insert(package, module);
+            if call_hook(package, "shutdown", &**module.read(), |m| m.shutdown()) {
+                self.lifecycle.set(package, ModuleState::ShutDown);
+                info!("module {:?} shut down", package);
+                report.stopped.push(package.clone());
+            } else {
+                self.lifecycle.set(package, ModuleState::Failed);
+                report.failed.push(package.clone());
+            }
+        }
     }
+}
+
+impl Modular {
+    /// Invocation made by `caller`, `None` for the host. The caller is only ever set by
+    /// the registry, so that modules can't pose as one another.
+    fn call<'a>(
+        &self,
+        caller: Option<Caller>,
+        package: &'a str,
+        method: &'a str,
+        data: Option<&'a [u8]>,
+        mut options: InvokeOptions,
+    ) -> Call<'a> {
+        let context = InvocationContext {
+            id: self.next_invocation.fetch_add(1, Ordering::Relaxed),
+            parent: options.parent.or_else(current_invocation),
+            caller,
+            headers: std::mem::take(&mut options.headers),
+        };
 
-    fn deregister_module(&self, package: &str) {
-        let m = self.modules.write().remove(package);
-        if m.is_none() {
-            error!("module {:?} not found", package);
-        } else {
-            info!("module {:?} deregistered", package);
+        Call {
+            package: Cow::Borrowed(package),
+            method: Cow::Borrowed(method),
+            data: data.map(Cow::Borrowed),
+            options,
+            context,
         }
     }
 
-    fn invoke(
+    pub(crate) fn invoke_from(
         &self,
+        caller: Option<Caller>,
         package: &str,
         method: &str,
         data: Option<&[u8]>,
         callback: Box<dyn Callback>,
-    ) {
-        let module = self.modules.read().get(package).cloned();
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        let callback = Box::new(Measured::new(
+            self.metrics.clone(),
+            package,
+            method,
+            callback,
+        ));
+        let call = self.call(caller, package, method, data, options);
+
+        Next::new(&self.interceptors, &|call, callback| {
+            self.dispatch(call, callback)
+        })
+        .run(call, callback)
+    }
+
+    pub(crate) fn invoke_stream_from(
+        &self,
+        caller: Option<Caller>,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        let callback = Box::new(Measured::new(
+            self.metrics.clone(),
+            package,
+            method,
+            callback,
+        ));
+        let call = self.call(caller, package, method, data, options);
+
+        Next::new(&self.interceptors, &|call, callback| {
+            self.dispatch_stream(call, callback)
+        })
+        .run(call, callback)
+    }
+
+    /// Invokes the module at the end of the interceptor chain.
+    fn dispatch(&self, call: Call, callback: Box<dyn Callback>) -> CancellationToken {
+        let Call {
+            package,
+            method,
+            data,
+            options,
+            context,
+        } = call;
+
+        let deadline = options.effective_deadline();
+        let module = self.resolve_invocation(&package, deadline);
+        let (module, callback) = match module {
+            Ok((id, v, pending)) => {
+                let callback: Box<dyn Callback> = Box::new(Tracked::new(pending, callback));
+                (Ok((id, v)), callback)
+            }
+            Err(e) => (Err(e), callback),
+        };
+        let callback = Arc::new(CallbackGuard::new(&package, &method, callback));
+        let token = self.invocation_token(&callback, deadline);
+
+        match module {
+            Ok(_) if is_expired(deadline) => callback.fail(timeout_error()),
+            Ok((id, v)) => with_current_invocation(context.id, || {
+                // the callback may complete before the module returns
+                let _invoking = self.in_flight.track(vec![Flight::To(id)]);
+                with_current_deadline(deadline, || {
+                    v.read().invoke(
+                        &method,
+                        data.as_deref(),
+                        Box::new(callback),
+                        token.clone(),
+                        context,
+                    )
+                })
+            }),
+            Err(e) => callback.on_error(module_not_found_error(&e)),
+        }
+
+        token
+    }
+
+    fn dispatch_stream(&self, call: Call, callback: Box<dyn StreamCallback>) -> CancellationToken {
+        let Call {
+            package,
+            method,
+            data,
+            options,
+            context,
+        } = call;
+
+        let deadline = options.effective_deadline();
+        let module = self.resolve_invocation(&package, deadline);
+        let (module, callback) = match module {
+            Ok((id, v, pending)) => {
+                let callback: Box<dyn StreamCallback> = Box::new(Tracked::new(pending, callback));
+                (Ok((id, v)), callback)
+            }
+            Err(e) => (Err(e), callback),
+        };
+        let callback = Arc::new(StreamCallbackGuard::new(&package, &method, callback));
+        let token = self.invocation_token(&callback, deadline);
 
         match module {
-            Some(v) => {
-                v.read().invoke(method, data, callback);
-            }
-            None => callback.on_error(CallbackError {
-                code: Error::ModuleNotFound as i32,
-                err_name: Error::ModuleNotFound.as_ref().into(),
-                description: Some(&format!("Module {:?} not found", package)),
-                data: None,
+            Ok(_) if is_expired(deadline) => callback.fail(timeout_error()),
+            Ok((id, v)) => with_current_invocation(context.id, || {
+                // the callback may complete before the module returns
+                let _invoking = self.in_flight.track(vec![Flight::To(id)]);
+                with_current_deadline(deadline, || {
+                    v.read().invoke_stream(
+                        &method,
+                        data.as_deref(),
+                        Box::new(callback),
+                        token.clone(),
+                        context,
+                    )
+                })
             }),
+            Err(e) => callback.on_error(module_not_found_error(&e)),
         }
+
+        token
+    }
+}
+
+impl Registry for Modular {
+    fn run(&self) -> Result<(), Error> {
+        let mut lock = self.is_running.lock();
+        if *lock {
+            return Err(Error::RegistryAlreadyRunning);
+        }
+        let modules = self.ordered_entities()?;
+        *lock = true;
+        drop(lock);
+
+        for (package, module, dependencies) in modules {
+            if self.start_module(&package, &module, &dependencies) {
+                self.spawn_run(package, module);
+            }
+        }
+
+        // modules that don't stop within the grace period of `shutdown` are marked failed
+        // and left behind, so wait on their state rather than their threads. Those swapped
+        // in meanwhile count too.
+        self.lifecycle
+            .wait_while_any_in(&[ModuleState::Running, ModuleState::Stopping]);
+
+        Ok(())
+    }
+
+    fn register_module(&self, module: Box<dyn Module>) -> Result<(), Error> {
+        let package = module.package().to_string();
+        let version = parse_version(&*module)?;
+
+        let module = Arc::new(RwLock::new(module));
+        let mut id = module_id(&package, &version);
+        let mut modules = self.modules.write();
+        let versions = modules.entry(package).or_default();
+        let mut replaced = vec![];
+
+        match (versions.get_mut(&version), self.duplicate_policy) {
+            (None, _) => {
+                versions.insert(version, Replicas::new(id.clone(), module));
+            }
+            (Some(_), DuplicatePolicy::Reject) => {
+                error!("module {:?} is already registered", id);
+                return Err(Error::ModuleAlreadyRegistered);
+            }
+            (Some(replicas), DuplicatePolicy::Replace) => {
+                replaced = replicas.take();
+                id = replicas.push(&id, module);
+                let ids: Vec<_> = replaced.iter().map(|(id, _)| id).collect();
+                info!("module {:?} replaces {:?}", id, ids);
+            }
+            (Some(replicas), DuplicatePolicy::Replicate) => {
+                id = replicas.push(&id, module);
+            }
+        }
+
+        info!("registering module {:?}", id);
+
+        let replaced_ids: Vec<_> = replaced.iter().map(|(id, _)| id.clone()).collect();
+        self.bindings.bind(split_id(&id).0, &id, &replaced_ids);
+        self.lifecycle.set_quietly(&id, ModuleState::Registered);
+        drop(modules);
+
+        self.lifecycle.emit(&id, ModuleState::Registered);
+
+        if !replaced.is_empty() {
+            // the replaced modules drain in the background, not holding up registration
+            let modular = self.clone();
+            thread::spawn(move || modular.drain(replaced));
+        }
+
+        self.check_dependencies(&id);
+
+        Ok(())
+    }
+
+    /// Deregisters the module `package` points to, along with its replicas. `package` may
+    /// be an address with a version requirement like for `invoke`. Returns once the
+    /// modules are drained, see `Modular::with_drain_timeout`, but when called through a
+    /// `ScopedRegistry`, which drains them in the background.
+    fn deregister_module(&self, package: &str) -> Result<(), Error> {
+        self.deregister(package, false)
+    }
+    fn methods(&self, package: &str) -> Option<Vec<MethodDescriptor>> {
+        let (_, module) = self.resolve(package).ok()?;
+        let methods = module.read().methods();
+        Some(methods)
+    }
+
+    fn find_modules(&self, query: &ModuleQuery) -> Vec<RegisteredModule> {
+        let modules = self.modules.read();
+
+        let mut packages = modules
+            .iter()
+            .filter(|(package, _)| match query {
+                ModuleQuery::All => true,
+                ModuleQuery::Exact(p) => *package == p,
+                ModuleQuery::Prefix(p) => package.starts_with(p.as_str()),
+                ModuleQuery::Glob(p) => glob_match(p, package),
+            })
+            .collect::<Vec<_>>();
+        packages.sort_unstable_by_key(|(package, _)| *package);
+
+        packages
+            .into_iter()
+            .flat_map(|(package, versions)| {
+                versions
+                    .iter()
+                    .map(move |(version, replicas)| RegisteredModule {
+                        package: package.clone(),
+                        version: version.to_string(),
+                        // replicas are all registered from the same package and version
+                        kind: replicas
+                            .iter()
+                            .next()
+                            .map(|(_, module)| module.read().kind())
+                            .unwrap_or_default(),
+                    })
+            })
+            .collect()
+    }
+
+    fn watch(&self, watcher: Box<dyn RegistryWatcher>) -> Watch {
+        // subscribed before listing, so that no registration falls in between
+        let id = self.lifecycle.watchers.add(watcher);
+        let modules = self.find_modules(&ModuleQuery::All);
+
+        Watch { id, modules }
+    }
+
+    fn unwatch(&self, id: u64) {
+        self.lifecycle.watchers.remove(id)
+    }
+
+    fn invoke_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        self.invoke_from(None, package, method, data, callback, options)
+    }
+
+    fn invoke_stream_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        self.invoke_stream_from(None, package, method, data, callback, options)
+    }
+}
+
+/// The guards `Modular` wraps callbacks in, so cancellation and timeouts can fail either kind.
+trait PendingCallback: Send + Sync {
+    fn fail(&self, err: CallbackError);
+    fn is_completed(&self) -> bool;
+}
+
+impl PendingCallback for CallbackGuard {
+    fn fail(&self, err: CallbackError) {
+        Callback::on_error(self, err)
+    }
+
+    fn is_completed(&self) -> bool {
+        CallbackGuard::is_completed(self)
+    }
+}
+
+impl PendingCallback for StreamCallbackGuard {
+    fn fail(&self, err: CallbackError) {
+        StreamCallback::on_error(self, err)
+    }
+
+    fn is_completed(&self) -> bool {
+        StreamCallbackGuard::is_completed(self)
+    }
+}
+
+/// The version of a module about to be registered, if its package and version are valid.
+fn parse_version(module: &dyn Module) -> Result<Version, Error> {
+    let package = module.package();
+
+    if package.contains('@') {
+        error!(
+            "module {:?} not registered, packages can't contain '@'",
+            package
+        );
+        return Err(Error::InvalidModule);
+    }
+
+    Version::parse(module.version()).map_err(|e| {
+        error!(
+            "module {:?} not registered, invalid version {:?}: {}",
+            package,
+            module.version(),
+            e
+        );
+        Error::InvalidModule
+    })
+}
+
+/// Hands the state of `from` over to `to`, returning why it failed if it did.
+fn hand_over(from: &ModularEntity, to: &ModularEntity) -> Result<(), String> {
+    let snapshot =
+        catch_panic(|| from.read().snapshot()).map_err(|e| format!("panic in snapshot: {}", e))?;
+
+    match snapshot {
+        Some(snapshot) => catch_panic(|| to.read().restore(&snapshot))
+            .map_err(|e| format!("panic in restore: {}", e))?,
+        None => Ok(()),
+    }
+}
+
+fn resolve_in(
+    modules: &HashMap<String, Versions>,
+    address: &str,
+) -> Result<(String, ModularEntity), String> {
+    let parsed = Address::parse(address)?;
+
+    modules
+        .get(parsed.package)
+        .and_then(|versions| parsed.resolve(versions))
+        .map(|(_, replicas)| replicas.pick().clone())
+        .ok_or_else(|| format!("Module {:?} not found", address))
+}
+
+fn is_expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| d <= Instant::now())
+}
+
+fn module_not_found_error(description: &str) -> CallbackError<'_> {
+    CallbackError {
+        code: Error::ModuleNotFound as i32,
+        err_name: Error::ModuleNotFound.as_ref().into(),
+        description: Some(description),
+        data: None,
+    }
+}
+
+fn cancelled_error() -> CallbackError<'static> {
+    CallbackError {
+        code: Error::Cancelled as i32,
+        err_name: Error::Cancelled.as_ref().into(),
+        description: Some("invocation cancelled by the caller"),
+        data: None,
+    }
+}
+
+fn timeout_error() -> CallbackError<'static> {
+    CallbackError {
+        code: Error::Timeout as i32,
+        err_name: Error::Timeout.as_ref().into(),
+        description: Some("invocation deadline exceeded"),
+        data: None,
     }
 }
 
@@ -118,3 +1119,71 @@ impl Registry for Modular {
 pub extern "C" fn create_modular() -> NativeRegistry {
     NativeRegistry::new(Modular::default())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on_stream;
+    use modular_core::{AsyncRegistry, CallbackSuccess};
+
+    /// Streams `0..count` before returning from `invoke_stream`.
+    struct Counter {
+        count: u32,
+    }
+
+    impl Module for Counter {
+        fn package(&self) -> &str {
+            "counter"
+        }
+
+        fn version(&self) -> &str {
+            "1.0.0"
+        }
+
+        fn run(&self) {}
+
+        fn invoke(
+            &self,
+            _method: &str,
+            _data: Option<&[u8]>,
+            _callback: Box<dyn Callback>,
+            _token: CancellationToken,
+            _context: InvocationContext,
+        ) {
+        }
+
+        fn invoke_stream(
+            &self,
+            _method: &str,
+            _data: Option<&[u8]>,
+            callback: Box<dyn StreamCallback>,
+            _token: CancellationToken,
+            _context: InvocationContext,
+        ) {
+            for i in 0..self.count {
+                callback.on_next(CallbackSuccess {
+                    data: Some(&i.to_le_bytes()),
+                });
+            }
+            callback.on_complete();
+        }
+    }
+
+    #[test]
+    fn async_stream_takes_a_synchronous_stream() {
+        let modular = Modular::default();
+        modular
+            .register_module(Box::new(Counter { count: 100 }))
+            .unwrap();
+
+        let stream = modular.invoke_stream_async("counter", "count", None);
+        let received = block_on_stream(stream)
+            .map(|r| {
+                let data = r.unwrap().data.unwrap();
+                u32::from_le_bytes(data.as_slice().try_into().unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,192 @@
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Access {
+    Allow,
+    Deny,
+}
+
+/// Grants or denies a caller invoking the methods of a package. Each field is a pattern
+/// in which `*` matches any run of characters, as in `dll.*` or `get_*`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct Rule {
+    pub caller: String,
+    pub package: String,
+    pub method: String,
+    pub access: Access,
+}
+
+impl Rule {
+    fn matches(&self, caller: &str, package: &str, method: &str) -> bool {
+        glob_match(&self.caller, caller)
+            && glob_match(&self.package, package)
+            && glob_match(&self.method, method)
+    }
+}
+
+/// An operation on the registry itself, rather than on a module, made through the registry
+/// handle of `Modular::scoped`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Operation {
+    Run,
+    Register,
+    Deregister,
+}
+
+/// Grants a caller an operation on the modules of a package; `package` is ignored for
+/// `Operation::Run`. The fields are patterns as for `Rule`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct OperationRule {
+    pub caller: String,
+    pub package: String,
+    pub operation: Operation,
+}
+
+impl OperationRule {
+    fn matches(&self, caller: &str, package: &str, operation: Operation) -> bool {
+        self.operation == operation
+            && glob_match(&self.caller, caller)
+            && (operation == Operation::Run || glob_match(&self.package, package))
+    }
+}
+
+/// Which modules may invoke what, enforced on calls made through the registry handles
+/// of `Modular::scoped`. A call matching a `Deny` rule is denied even if an `Allow` rule
+/// matches it as well; one matching no rule gets the default access.
+///
+/// Operations on the registry itself are denied unless an `OperationRule` grants them,
+/// whatever the default access.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Permissions {
+    rules: Vec<Rule>,
+    default: Access,
+    operations: Vec<OperationRule>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+impl Permissions {
+    pub fn allow_all() -> Self {
+        Self {
+            rules: vec![],
+            default: Access::Allow,
+            operations: vec![],
+        }
+    }
+
+    pub fn deny_all() -> Self {
+        Self {
+            rules: vec![],
+            default: Access::Deny,
+            operations: vec![],
+        }
+    }
+
+    pub fn allow(self, caller: &str, package: &str, method: &str) -> Self {
+        self.rule(caller, package, method, Access::Allow)
+    }
+
+    pub fn deny(self, caller: &str, package: &str, method: &str) -> Self {
+        self.rule(caller, package, method, Access::Deny)
+    }
+
+    pub fn rule(mut self, caller: &str, package: &str, method: &str, access: Access) -> Self {
+        self.rules.push(Rule {
+            caller: caller.to_string(),
+            package: package.to_string(),
+            method: method.to_string(),
+            access,
+        });
+        self
+    }
+
+    pub fn allow_operation(mut self, caller: &str, package: &str, operation: Operation) -> Self {
+        self.operations.push(OperationRule {
+            caller: caller.to_string(),
+            package: package.to_string(),
+            operation,
+        });
+        self
+    }
+
+    /// Access of `caller` to `method` of `package`, a package name without version requirement.
+    pub fn check(&self, caller: &str, package: &str, method: &str) -> Access {
+        let matching = self
+            .rules
+            .iter()
+            .filter(|r| r.matches(caller, package, method))
+            .map(|r| r.access)
+            .collect::<Vec<_>>();
+
+        if matching.contains(&Access::Deny) {
+            Access::Deny
+        } else if matching.is_empty() {
+            self.default
+        } else {
+            Access::Allow
+        }
+    }
+
+    /// Access of `caller` to `operation` on the modules of `package`, a package name without
+    /// version requirement.
+    pub fn check_operation(&self, caller: &str, package: &str, operation: Operation) -> Access {
+        if self
+            .operations
+            .iter()
+            .any(|r| r.matches(caller, package, operation))
+        {
+            Access::Allow
+        } else {
+            Access::Deny
+        }
+    }
+}
+
+/// Matches `value` against `pattern`, where `*` matches any run of characters.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there is always a first part, empty if the pattern starts with `*`
+    let first = parts.next().unwrap_or_default();
+
+    let mut rest = match value.strip_prefix(first) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // no `*` at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
@@ -0,0 +1,267 @@
+use crate::address::split_id;
+use crate::drain::Tracked;
+use crate::modular::Modular;
+use crate::permissions::{Access, Operation};
+use modular_core::{
+    Callback, CallbackError, Caller, CancellationToken, Error, InvokeOptions, MethodDescriptor,
+    Module, ModuleQuery, RegisteredModule, Registry, RegistryWatcher, StreamCallback, Watch,
+};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use tracing::warn;
+
+/// The registry handle of one module, created with `Modular::scoped` and handed to the
+/// module instead of a plain clone, so its invocations, and operations on the registry, are
+/// checked against the `Permissions` of the registry as coming from `caller`, and its
+/// invocations reach the invoked module with `caller` in their `InvocationContext`.
+///
+/// The handle is bound to the module registered next for its package, or swapped in for
+/// it, so each module should be created with a handle of its own.
+#[derive(Clone)]
+pub struct ScopedRegistry {
+    modular: Modular,
+    caller: Arc<str>,
+    binding: Binding,
+}
+
+impl ScopedRegistry {
+    pub(crate) fn new(modular: Modular, caller: &str, binding: Binding) -> Self {
+        Self {
+            modular,
+            caller: caller.into(),
+            binding,
+        }
+    }
+
+    pub fn caller(&self) -> &str {
+        &self.caller
+    }
+
+    /// The id of the module the handle is bound to, `None` until it is registered.
+    pub fn module_id(&self) -> Option<String> {
+        self.binding.read().clone()
+    }
+
+    /// Counts an invocation the module makes in flight for it until `callback` completes,
+    /// so that the module isn't dropped while the callback may still run its code.
+    fn track<C: ?Sized>(&self, callback: Box<C>) -> Tracked<C> {
+        // a module that isn't registered yet isn't drained either
+        let id = self.module_id().unwrap_or_default();
+        Tracked::new(self.modular.track_outgoing(id), callback)
+    }
+
+    /// The caller as invoked modules see it, with the version of the module it is bound to,
+    /// or else the highest registered one.
+    fn identity(&self) -> Caller {
+        match &*self.binding.read() {
+            Some(id) => {
+                let (package, version) = split_id(id);
+                Caller {
+                    package: package.to_string(),
+                    version: version.to_string(),
+                }
+            }
+            None => Caller {
+                package: package_of(&self.caller).to_string(),
+                version: self
+                    .modular
+                    .resolve_version(&self.caller)
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+
+    /// Whether the caller may invoke `method` of the module `package` points to, auditing
+    /// the call if it may not.
+    fn permit(&self, package: &str, method: &str) -> Result<(), String> {
+        // the requirement of an address doesn't matter to permissions
+        let target = package_of(package);
+
+        match self
+            .modular
+            .permissions()
+            .check(&self.caller, target, method)
+        {
+            Access::Allow => Ok(()),
+            Access::Deny => {
+                warn!(
+                    target: "modular::audit",
+                    caller = %self.caller,
+                    package = %package,
+                    method = %method,
+                    "invocation denied"
+                );
+                Err(format!(
+                    "module {:?} may not invoke {:?} of {:?}",
+                    self.caller, method, package
+                ))
+            }
+        }
+    }
+
+    /// Whether the caller may make `operation` on the modules of `package`, auditing the
+    /// operation if it may not.
+    fn permit_operation(&self, package: &str, operation: Operation) -> Result<(), Error> {
+        let target = package_of(package);
+
+        match self
+            .modular
+            .permissions()
+            .check_operation(&self.caller, target, operation)
+        {
+            Access::Allow => Ok(()),
+            Access::Deny => {
+                warn!(
+                    target: "modular::audit",
+                    caller = %self.caller,
+                    package = %package,
+                    operation = ?operation,
+                    "operation denied"
+                );
+                Err(Error::PermissionDenied)
+            }
+        }
+    }
+}
+
+fn package_of(address: &str) -> &str {
+    address.split_once('@').map_or(address, |(p, _)| p)
+}
+
+/// The id of the module a `ScopedRegistry` is bound to, shared by its clones.
+pub(crate) type Binding = Arc<BindingSlot>;
+
+type BindingSlot = RwLock<Option<String>>;
+
+/// The `ScopedRegistry` handles of each package, for `Modular` to bind them to the modules
+/// registered.
+#[derive(Default)]
+pub(crate) struct Bindings {
+    handles: Mutex<HashMap<String, Vec<Weak<BindingSlot>>>>,
+}
+
+impl Bindings {
+    pub fn new_binding(&self, caller: &str) -> Binding {
+        let binding = Binding::default();
+        let mut handles = self.handles.lock();
+        let package = handles.entry(package_of(caller).to_string()).or_default();
+
+        package.retain(|b| b.strong_count() > 0);
+        package.push(Arc::downgrade(&binding));
+
+        binding
+    }
+
+    /// Binds the handles of `package` that aren't bound yet, or are bound to one of the
+    /// `replaced` modules, to the module `id`.
+    pub fn bind(&self, package: &str, id: &str, replaced: &[String]) {
+        let mut handles = self.handles.lock();
+        let Some(package) = handles.get_mut(package) else {
+            return;
+        };
+
+        package.retain(|binding| {
+            let Some(binding) = binding.upgrade() else {
+                return false;
+            };
+
+            let mut binding = binding.write();
+            if binding.as_ref().is_none_or(|b| replaced.contains(b)) {
+                *binding = Some(id.to_string());
+            }
+            true
+        });
+    }
+}
+
+fn permission_denied_error(description: &str) -> CallbackError<'_> {
+    CallbackError {
+        code: Error::PermissionDenied as i32,
+        err_name: Error::PermissionDenied.as_ref().into(),
+        description: Some(description),
+        data: None,
+    }
+}
+
+impl Registry for ScopedRegistry {
+    fn run(&self) -> Result<(), Error> {
+        self.permit_operation("", Operation::Run)?;
+        self.modular.run()
+    }
+
+    fn register_module(&self, module: Box<dyn Module>) -> Result<(), Error> {
+        self.permit_operation(module.package(), Operation::Register)?;
+        self.modular.register_module(module)
+    }
+
+    fn deregister_module(&self, package: &str) -> Result<(), Error> {
+        self.permit_operation(package, Operation::Deregister)?;
+        self.modular.deregister(package, true)
+    }
+
+    fn methods(&self, package: &str) -> Option<Vec<MethodDescriptor>> {
+        self.modular.methods(package)
+    }
+
+    fn find_modules(&self, query: &ModuleQuery) -> Vec<RegisteredModule> {
+        self.modular.find_modules(query)
+    }
+
+    fn watch(&self, watcher: Box<dyn RegistryWatcher>) -> Watch {
+        self.modular.watch(watcher)
+    }
+
+    fn unwatch(&self, id: u64) {
+        self.modular.unwatch(id)
+    }
+
+    fn invoke_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn Callback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        match self.permit(package, method) {
+            Ok(()) => self.modular.invoke_from(
+                Some(self.identity()),
+                package,
+                method,
+                data,
+                Box::new(self.track(callback)),
+                options,
+            ),
+            Err(e) => {
+                callback.on_error(permission_denied_error(&e));
+                CancellationToken::new()
+            }
+        }
+    }
+
+    fn invoke_stream_with(
+        &self,
+        package: &str,
+        method: &str,
+        data: Option<&[u8]>,
+        callback: Box<dyn StreamCallback>,
+        options: InvokeOptions,
+    ) -> CancellationToken {
+        match self.permit(package, method) {
+            Ok(()) => self.modular.invoke_stream_from(
+                Some(self.identity()),
+                package,
+                method,
+                data,
+                Box::new(self.track(callback)),
+                options,
+            ),
+            Err(e) => {
+                callback.on_error(permission_denied_error(&e));
+                CancellationToken::new()
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// Packages `Modular::swap_module` is swapping. Invocations of them wait until it is done,
+/// so that they are served by the module swapped in.
+#[derive(Default)]
+pub(crate) struct Swaps {
+    packages: Mutex<HashSet<String>>,
+    done: Condvar,
+}
+
+impl Swaps {
+    /// Marks `package` as being swapped until the returned guard is dropped, once any
+    /// other swap of it is done.
+    pub fn begin(&self, package: &str) -> Swapping<'_> {
+        let mut packages = self.packages.lock();
+        while packages.contains(package) {
+            self.done.wait(&mut packages);
+        }
+        packages.insert(package.to_string());
+
+        Swapping {
+            swaps: self,
+            package: package.to_string(),
+        }
+    }
+
+    pub fn is_swapping(&self, package: &str) -> bool {
+        self.packages.lock().contains(package)
+    }
+
+    /// Blocks while `package` is being swapped, or until `deadline` passes.
+    pub fn wait(&self, package: &str, deadline: Option<Instant>) {
+        let mut packages = self.packages.lock();
+
+        while packages.contains(package) {
+            match deadline {
+                Some(deadline) => {
+                    if self.done.wait_until(&mut packages, deadline).timed_out() {
+                        return;
+                    }
+                }
+                None => self.done.wait(&mut packages),
+            }
+        }
+    }
+}
+
+pub(crate) struct Swapping<'a> {
+    swaps: &'a Swaps,
+    package: String,
+}
+
+impl Drop for Swapping<'_> {
+    fn drop(&mut self) {
+        self.swaps.packages.lock().remove(&self.package);
+        self.swaps.done.notify_all();
+    }
+}
@@ -0,0 +1,126 @@
+use parking_lot::{Condvar, Mutex};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tracing::error;
+
+type Action = Box<dyn FnOnce() + Send>;
+
+struct Entry {
+    at: Instant,
+    seq: u64,
+    action: Action,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // reversed, so that the max-heap pops the earliest entry first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    wakeup: Condvar,
+}
+
+/// Runs actions at a given instant on a single background thread, started lazily.
+pub(crate) struct Timer {
+    shared: Arc<Shared>,
+    started: Mutex<bool>,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared::default()),
+            started: Mutex::new(false),
+        }
+    }
+
+    pub fn schedule<F: FnOnce() + Send + 'static>(&self, at: Instant, action: F) {
+        self.start();
+
+        let mut queue = self.shared.queue.lock();
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.entries.push(Entry {
+            at,
+            seq,
+            action: Box::new(action),
+        });
+        drop(queue);
+
+        self.shared.wakeup.notify_one();
+    }
+
+    fn start(&self) {
+        let mut started = self.started.lock();
+        if *started {
+            return;
+        }
+        *started = true;
+
+        let shared = self.shared.clone();
+        thread::spawn(move || Self::run(&shared));
+    }
+
+    fn run(shared: &Shared) {
+        let mut queue = shared.queue.lock();
+
+        while !queue.stopped {
+            let at = match queue.entries.peek() {
+                Some(entry) => entry.at,
+                None => {
+                    shared.wakeup.wait(&mut queue);
+                    continue;
+                }
+            };
+
+            if at > Instant::now() {
+                shared.wakeup.wait_until(&mut queue, at);
+                continue;
+            }
+
+            let entry = queue.entries.pop().unwrap();
+
+            // actions call back into user code, which must not run under the lock
+            drop(queue);
+            if let Err(e) = modular_core::catch_panic(entry.action) {
+                error!("panic in timer action: {}", e);
+            }
+            queue = shared.queue.lock();
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.queue.lock().stopped = true;
+        self.shared.wakeup.notify_one();
+    }
+}